@@ -14,7 +14,8 @@ use std::io;
 #[derive(Debug)]
 #[deprecated(note = "use lapin directly instead")]
 pub struct Error {
-    inner: Context<ErrorKind>,
+    // Boxed to keep the `Result`s small, the context holds a backtrace and possibly a lapin_futures error
+    inner: Box<Context<ErrorKind>>,
 }
 
 /// The different kinds of errors that can be reported.
@@ -33,6 +34,21 @@ pub enum ErrorKind {
     /// Failure to connect
     #[fail(display = "Failed to connect: {}", _0)]
     ConnectionFailed(#[fail(cause)] io::Error),
+    /// Failure to configure the TLS engine
+    #[fail(display = "Invalid TLS configuration: {}", _0)]
+    InvalidTlsConfiguration(#[fail(cause)] io::Error),
+    /// Failure to perform the TLS handshake
+    #[fail(display = "TLS handshake failed: {}", _0)]
+    TlsHandshakeFailed(#[fail(cause)] io::Error),
+    /// The TLS handshake and AMQP negotiation didn't complete in time
+    #[fail(display = "Timed out during the handshake")]
+    HandshakeTimedOut,
+    /// The certificate presented by the server doesn't match the pinned fingerprint
+    #[fail(display = "Server certificate doesn't match the pinned fingerprint")]
+    PinnedCertificateMismatch,
+    /// The connection was cancelled before completing
+    #[fail(display = "Connection cancelled")]
+    Cancelled,
     /// Error from lapin_futures
     #[fail(display = "Protocol error: {:?}", _0)]
     ProtocolError(#[fail(cause)] lapin_futures::error::Error),
@@ -74,12 +90,28 @@ impl fmt::Display for Error {
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
-        Error { inner: Context::new(kind) }
+        Error { inner: Box::new(Context::new(kind)) }
     }
 }
 
 impl From<Context<ErrorKind>> for Error {
     fn from(inner: Context<ErrorKind>) -> Error {
-        Error { inner: inner }
+        Error { inner: Box::new(inner) }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match *err.kind() {
+            ErrorKind::UriParsingError(_)             => io::ErrorKind::InvalidInput,
+            ErrorKind::InvalidDomainName(_)           => io::ErrorKind::NotFound,
+            ErrorKind::ConnectionFailed(ref e)        => e.kind(),
+            ErrorKind::InvalidTlsConfiguration(ref e) => e.kind(),
+            ErrorKind::TlsHandshakeFailed(ref e)      => e.kind(),
+            ErrorKind::HandshakeTimedOut              => io::ErrorKind::TimedOut,
+            ErrorKind::PinnedCertificateMismatch      => io::ErrorKind::InvalidData,
+            _                                         => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err.compat())
     }
 }
@@ -0,0 +1,153 @@
+use futures::{Async, Future, Poll};
+use tokio_timer::Delay;
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The time to wait for a connection attempt before starting the next one, in milliseconds, as recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
+/// Connect to the first reachable address, starting a new attempt every `CONNECTION_ATTEMPT_DELAY_MS`
+/// or as soon as the previous one failed, alternating between address families.
+pub(crate) struct HappyEyeballs<T, Connect> {
+    addrs:      VecDeque<SocketAddr>,
+    connect:    Connect,
+    attempts:   Vec<Box<dyn Future<Item = T, Error = io::Error> + Send + 'static>>,
+    delay:      Delay,
+    last_error: Option<io::Error>,
+}
+
+impl<T, Connect: FnMut(SocketAddr) -> Box<dyn Future<Item = T, Error = io::Error> + Send + 'static>> HappyEyeballs<T, Connect> {
+    pub(crate) fn new(addrs: Vec<SocketAddr>, connect: Connect) -> HappyEyeballs<T, Connect> {
+        HappyEyeballs {
+            addrs:      interleave(addrs),
+            connect,
+            attempts:   Vec::new(),
+            delay:      Delay::new(Instant::now()),
+            last_error: None,
+        }
+    }
+}
+
+impl<T, Connect: FnMut(SocketAddr) -> Box<dyn Future<Item = T, Error = io::Error> + Send + 'static>> Future for HappyEyeballs<T, Connect> {
+    type Item  = T;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<T, io::Error> {
+        loop {
+            let mut failed = false;
+            let mut i      = 0;
+            while i < self.attempts.len() {
+                match self.attempts[i].poll() {
+                    Ok(Async::Ready(stream)) => return Ok(Async::Ready(stream)),
+                    Ok(Async::NotReady)      => i += 1,
+                    Err(err)                 => {
+                        drop(self.attempts.swap_remove(i));
+                        self.last_error = Some(err);
+                        failed          = true;
+                    },
+                }
+            }
+
+            if self.addrs.is_empty() {
+                if self.attempts.is_empty() {
+                    return Err(self.last_error.take().unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No address to connect to")));
+                }
+                return Ok(Async::NotReady);
+            }
+
+            // A timer error shouldn't prevent us from connecting, just start the next attempt right away
+            let delay_elapsed = self.delay.poll().map(|delay| delay.is_ready()).unwrap_or(true);
+            if !(failed || delay_elapsed || self.attempts.is_empty()) {
+                return Ok(Async::NotReady);
+            }
+
+            if let Some(addr) = self.addrs.pop_front() {
+                self.attempts.push((self.connect)(addr));
+                self.delay.reset(Instant::now() + Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS));
+            }
+        }
+    }
+}
+
+/// Alternate between address families, starting with the family of the first address
+fn interleave(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let first_is_ipv6 = addrs.first().map(SocketAddr::is_ipv6).unwrap_or(false);
+    let (mut preferred, mut fallback): (VecDeque<_>, VecDeque<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let mut interleaved = VecDeque::with_capacity(preferred.len() + fallback.len());
+    while !preferred.is_empty() || !fallback.is_empty() {
+        interleaved.extend(preferred.pop_front());
+        interleaved.extend(fallback.pop_front());
+    }
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+
+    use std::sync::{Arc, Mutex};
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    /// Connect to `addrs`, an attempt hanging on port 1, failing on port 2 and succeeding right away with its port otherwise
+    fn race(addrs: Vec<SocketAddr>) -> (io::Result<u16>, Vec<SocketAddr>, Duration) {
+        let attempted = Arc::new(Mutex::new(Vec::new()));
+        let recorder  = attempted.clone();
+        let start     = Instant::now();
+        let res       = tokio::runtime::current_thread::block_on_all(HappyEyeballs::new(addrs, move |addr: SocketAddr| {
+            recorder.lock().unwrap().push(addr);
+            match addr.port() {
+                1    => Box::new(future::empty()) as Box<dyn Future<Item = u16, Error = io::Error> + Send + 'static>,
+                2    => Box::new(future::err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("{} refused", addr)))),
+                port => Box::new(future::ok(port)),
+            }
+        }));
+        let attempted = attempted.lock().unwrap().clone();
+        (res, attempted, start.elapsed())
+    }
+
+    #[test]
+    fn interleave_address_families() {
+        assert_eq!(Vec::from(interleave(addrs(&["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1"]))), addrs(&["[::1]:1", "10.0.0.1:1", "[::2]:1", "[::3]:1"]));
+        assert_eq!(Vec::from(interleave(addrs(&["10.0.0.1:1", "10.0.0.2:1", "[::1]:1", "[::2]:1"]))), addrs(&["10.0.0.1:1", "[::1]:1", "10.0.0.2:1", "[::2]:1"]));
+        assert_eq!(Vec::from(interleave(addrs(&["10.0.0.1:1", "10.0.0.2:1"]))), addrs(&["10.0.0.1:1", "10.0.0.2:1"]));
+        assert!(interleave(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn start_next_attempt_on_failure() {
+        let (res, attempted, elapsed) = race(addrs(&["10.0.0.1:2", "[::1]:2", "10.0.0.2:3"]));
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(attempted, addrs(&["10.0.0.1:2", "[::1]:2", "10.0.0.2:3"]));
+        assert!(elapsed < Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn start_next_attempt_after_delay() {
+        let (res, attempted, elapsed) = race(addrs(&["[::1]:1", "10.0.0.1:3"]));
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(attempted, addrs(&["[::1]:1", "10.0.0.1:3"]));
+        assert!(elapsed >= Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn report_last_error() {
+        let (res, attempted, _) = race(addrs(&["10.0.0.1:2", "10.0.0.2:2"]));
+        assert_eq!(res.unwrap_err().to_string(), "10.0.0.2:2 refused");
+        assert_eq!(attempted.len(), 2);
+    }
+
+    #[test]
+    fn fail_without_address() {
+        let (res, attempted, _) = race(Vec::new());
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(attempted.is_empty());
+    }
+}
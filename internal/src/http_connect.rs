@@ -0,0 +1,130 @@
+use futures::{self, future::{Future, Loop}};
+use tokio_io::io::{read_exact, write_all};
+
+use std::io;
+use std::str;
+
+use crate::TcpStream;
+
+const MAX_RESPONSE_SIZE: usize = 8192;
+
+/// Send an HTTP `CONNECT` request on `stream`, asking the proxy to open a tunnel to `host:port`
+pub(crate) fn connect(stream: TcpStream, host: String, port: u16) -> Box<dyn Future<Item = TcpStream, Error = io::Error> + Send + 'static> {
+    let authority = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    let request   = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", authority);
+    Box::new(
+        write_all(stream, request.into_bytes()).and_then(|(stream, _)| {
+            // Read one byte at a time so that nothing past the response headers gets consumed
+            futures::future::loop_fn((stream, Vec::new()), |(stream, mut response)| {
+                read_exact(stream, [0u8; 1]).and_then(move |(stream, byte)| {
+                    response.push(byte[0]);
+                    if response.ends_with(b"\r\n\r\n") {
+                        Ok(Loop::Break((stream, response)))
+                    } else if response.len() >= MAX_RESPONSE_SIZE {
+                        Err(error("HTTP proxy response too long"))
+                    } else {
+                        Ok(Loop::Continue((stream, response)))
+                    }
+                })
+            })
+        }).and_then(|(stream, response)| {
+            match status(&response) {
+                Some(200)    => Ok(stream),
+                Some(status) => Err(error(&format!("HTTP proxy failed to connect: status {}", status))),
+                None         => Err(error("Invalid HTTP proxy reply")),
+            }
+        })
+    )
+}
+
+/// Extract the status code from the status line, e.g. `HTTP/1.1 200 Connection established`
+fn status(response: &[u8]) -> Option<u16> {
+    let line      = str::from_utf8(response).ok()?.lines().next()?;
+    let mut parts = line.split(' ');
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+fn error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+
+    use std::io::{Read, Write};
+    use std::net::{self, Ipv4Addr, TcpListener};
+    use std::thread;
+
+    /// Run `proxy` on the accepted connection, given the request it received, while asking for a tunnel to `host:port`
+    fn tunnel<F: FnOnce(net::TcpStream, String) + Send + 'static>(host: &str, port: u16, proxy: F) -> io::Result<Vec<u8>> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr     = listener.local_addr().unwrap();
+        let proxy    = thread::spawn(move || {
+            let mut stream  = listener.accept().unwrap().0;
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8; 1];
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            proxy(stream, String::from_utf8(request).unwrap())
+        });
+        let host     = host.to_string();
+        let res      = tokio::runtime::current_thread::block_on_all(TcpStream::connect(&addr).and_then(move |stream| connect(stream, host, port)).and_then(|stream| {
+            // Whatever follows the response headers is left to the caller
+            tokio_io::io::read_to_end(stream, Vec::new()).map(|(_, data)| data)
+        }));
+        proxy.join().unwrap();
+        res
+    }
+
+    #[test]
+    fn parse_status() {
+        assert_eq!(status(b"HTTP/1.1 200 Connection established\r\n\r\n"), Some(200));
+        assert_eq!(status(b"HTTP/1.0 407 Proxy Authentication Required\r\n\r\n"), Some(407));
+        assert_eq!(status(b"HTTP/2 200\r\n\r\n"), None);
+        assert_eq!(status(b"SSH-2.0-OpenSSH\r\n\r\n"), None);
+        assert_eq!(status(b"HTTP/1.1 OK\r\n\r\n"), None);
+        assert_eq!(status(b"\xff\xfe\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn open_tunnel() {
+        let data = tunnel("broker", 5671, |mut stream, request| {
+            assert_eq!(request, "CONNECT broker:5671 HTTP/1.1\r\nHost: broker:5671\r\n\r\n");
+            stream.write_all(b"HTTP/1.1 200 Connection established\r\nProxy-Agent: test\r\n\r\nAMQP").unwrap();
+        }).unwrap();
+        assert_eq!(data, b"AMQP");
+    }
+
+    #[test]
+    fn bracket_ipv6_targets() {
+        tunnel("2001:db8::1", 5672, |mut stream, request| {
+            assert_eq!(request, "CONNECT [2001:db8::1]:5672 HTTP/1.1\r\nHost: [2001:db8::1]:5672\r\n\r\n");
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        }).unwrap();
+    }
+
+    #[test]
+    fn report_refused_tunnel() {
+        let err = tunnel("broker", 5671, |mut stream, _| {
+            stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+        }).unwrap_err();
+        assert!(err.to_string().contains("status 403"), "{}", err);
+    }
+
+    #[test]
+    fn reject_too_long_response() {
+        let err = tunnel("broker", 5671, |mut stream, _| {
+            // The client gives up before the whole response is written
+            let _ = stream.write_all(format!("HTTP/1.1 200 OK\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_RESPONSE_SIZE)).as_bytes());
+        }).unwrap_err();
+        assert!(err.to_string().contains("too long"), "{}", err);
+    }
+}
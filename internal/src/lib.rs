@@ -1,6 +1,6 @@
 #![deny(missing_docs)]
 #![warn(rust_2018_idioms)]
-#![doc(html_root_url = "https://docs.rs/lapin-futures-tls-internal/0.7.1/")]
+#![doc(html_root_url = "https://docs.rs/lapin-futures-tls-internal/0.8.0/")]
 #![recursion_limit="128"]
 
 //! lapin-futures-openssl
@@ -61,50 +61,266 @@ pub mod lapin;
 /// Reexport of the `uri` module from the `amq_protocol` crate
 #[deprecated(note = "use lapin directly instead")]
 pub mod uri;
+/// A fake broker to test the connection process against
+#[cfg(feature = "test-util")]
+#[deprecated(note = "use lapin directly instead")]
+pub mod test_util;
+
+mod happy_eyeballs;
+mod http_connect;
+mod proxy_env;
+mod proxy_protocol;
+mod query;
+mod resolver;
+mod retry;
+mod socks;
+
+pub use resolver::{CachingResolver, Resolver, SystemResolver};
+pub use retry::RetryPolicy;
 
 /// Reexport of `TcpStream`
 #[deprecated(note = "use lapin directly instead")]
 pub use tokio_tcp::TcpStream;
 
+/// Reexport of `UnixStream`
+#[cfg(unix)]
+#[deprecated(note = "use lapin directly instead")]
+pub use tokio_uds::UnixStream;
+
 use bytes::{Buf, BufMut};
 use failure;
-use futures::{self, future::Future, Poll};
+use futures::{self, future::Future, sync::mpsc::UnboundedSender, Async, Poll};
+use iovec::IoVec;
+use log::debug;
+use net2::{TcpBuilder, TcpStreamExt};
 use tokio_executor;
 use tokio_io::{AsyncRead, AsyncWrite};
-use trust_dns_resolver::AsyncResolver;
+use tokio_reactor::Handle;
+use tokio_timer::{timeout, Timeout};
 
+use std::fmt;
 use std::io::{self, Read, Write};
-use std::net::SocketAddr;
+use std::net::{self, SocketAddr};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use error::{Error, ErrorKind};
-use lapin::client::{ConnectionOptions, ConnectionProperties};
+use happy_eyeballs::HappyEyeballs;
+use proxy_env::ProxyKind;
+use lapin::client::{ConnectionConfiguration, ConnectionOptions, ConnectionProperties};
+use lapin::types::AMQPValue;
 use uri::{AMQPScheme, AMQPUri};
 
-/// Represents either a raw `TcpStream` or a `TlsStream`.
+/// The `Future` returned by the connect methods, providing the `Client`, its `HeartbeatHandle` and the heartbeat pulse
+type ConnectFuture<TlsStream> = Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static>;
+
+/// The `Future` resolving the addresses of a host
+type ResolveFuture = Box<dyn Future<Item = Vec<SocketAddr>, Error = Error> + Send + 'static>;
+
+/// Represents either a raw `TcpStream`, a `TlsStream` or, on unix, a `UnixStream`.
 /// The `TlsStream` is wrapped in a `Box` to keep the enum footprint minimal.
+///
+/// The `Uds` variant was added in 0.8.0, code matching exhaustively on this enum has to handle it.
 #[deprecated(note = "use lapin directly instead")]
 pub enum AMQPStream<TlsStream: AsyncRead + AsyncWrite + Send + 'static> {
     /// The raw `TcpStream` used for basic AMQP connections.
     Raw(TcpStream),
     /// The `TlsStream` used for AMQPs connections.
     Tls(Box<TlsStream>),
+    /// The `UnixStream` used for AMQP connections over a Unix domain socket, without TLS.
+    #[cfg(unix)]
+    Uds(UnixStream),
+}
+
+/// Options controlling how the underlying `TcpStream` of an `AMQPStream` is opened.
+#[derive(Clone, Debug)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct AMQPStreamOptions {
+    /// The maximum time to wait for the TCP connection to be established, no limit if `None`
+    ///
+    /// When connecting using a `&str`, it defaults to the `connection_timeout` query parameter, in milliseconds.
+    pub connect_timeout:   Option<Duration>,
+    /// The maximum time to wait for the TLS handshake and AMQP negotiation once the TCP connection is established, no limit if `None`
+    ///
+    /// The connection fails with `ErrorKind::HandshakeTimedOut` when it expires.
+    pub handshake_timeout: Option<Duration>,
+    /// The address to connect to, skipping the resolution of the uri host if set
+    ///
+    /// The uri host is still used as the TLS server name unless `server_name` is set.
+    pub addr:              Option<SocketAddr>,
+    /// The SOCKS5 proxy to connect through, connecting directly if `None`
+    ///
+    /// The broker host is resolved by the proxy unless `addr` is set, and is still used as the TLS server name unless `server_name` is set.
+    pub proxy:             Option<SocketAddr>,
+    /// The HTTP proxy to open a tunnel through with a `CONNECT` request, connecting directly if `None`
+    ///
+    /// It is used like the SOCKS5 `proxy`, which takes precedence if both are set.
+    pub http_proxy:        Option<SocketAddr>,
+    /// Whether to use the proxy configured in the environment when neither `proxy` nor `http_proxy` is set, defaults to `false`
+    ///
+    /// The proxy is read from `ALL_PROXY`, then `HTTPS_PROXY`, as a `socks5://`, `socks5h://` or `http://` url,
+    /// the last one being the default without a scheme. The uri host is resolved locally with `socks5://`, and by the proxy with `socks5h://`. The uri host isn't proxied if it's listed in `NO_PROXY`,
+    /// which holds comma separated host names, domain suffixes or IP addresses, optionally with a port, or `*`.
+    pub proxy_from_env:    bool,
+    /// The name sent to the server as SNI and checked against its certificate, defaults to the uri host if `None`
+    ///
+    /// When connecting using a `&str`, it defaults to the `server_name_indication` query parameter.
+    /// A server name given directly to the TLS connector takes precedence.
+    pub server_name:       Option<String>,
+    /// Whether to check the server certificate against the server name, defaults to `true`
+    ///
+    /// When connecting using a `&str`, it is disabled by the `verify_hostname=false` query parameter. The certificate
+    /// chain is still verified. Only the `lapin-futures-rustls` `AMQPConnectionBuilder` built with the `dangerous-tls`
+    /// feature supports disabling it, the TLS handshake fails with `ErrorKind::TlsHandshakeFailed` otherwise.
+    pub verify_hostname:   bool,
+    /// The source and destination addresses to announce in a PROXY protocol v2 header, none is sent if `None`
+    ///
+    /// The header is sent right after the TCP connection is established, before the TLS handshake.
+    pub proxy_protocol:    Option<(SocketAddr, SocketAddr)>,
+    /// Whether to set `TCP_NODELAY` on the socket, disabling Nagle's algorithm, defaults to `true`
+    pub nodelay:           bool,
+    /// The idle time after which `SO_KEEPALIVE` probes are sent, keepalive is disabled if `None`
+    pub keepalive:         Option<Duration>,
+    /// The local address to bind the socket to before connecting, letting the OS pick one if `None`
+    pub local_addr:        Option<SocketAddr>,
+    /// The only IP family to connect over, trying both IPv4 and IPv6 addresses if `None`
+    ///
+    /// The addresses of the other family are ignored, the connection fails if none is left.
+    /// When connecting through a proxy, this applies to the addresses of the proxy.
+    pub ip_family:         Option<IpFamily>,
+    /// The name of the network interface to bind the socket to (`SO_BINDTODEVICE`), using the routing table if `None`
+    ///
+    /// This usually requires the `CAP_NET_RAW` capability.
+    #[cfg(target_os = "linux")]
+    pub bind_device:       Option<String>,
+    /// The size of the socket receive buffer (`SO_RCVBUF`), using the OS default if `None`
+    ///
+    /// The OS may adjust the requested size, e.g. Linux doubles it and clamps it to `net.core.rmem_max`.
+    pub recv_buffer_size:  Option<usize>,
+    /// The size of the socket send buffer (`SO_SNDBUF`), using the OS default if `None`
+    ///
+    /// The OS may adjust the requested size, e.g. Linux doubles it and clamps it to `net.core.wmem_max`.
+    pub send_buffer_size:  Option<usize>,
+    /// The heartbeat timeout to negotiate with the broker, in seconds, taking precedence over the uri if set
+    pub heartbeat:         Option<u16>,
+    /// The maximum `frame_max` to negotiate with the broker, whatever the uri or the broker ask for, no limit if `None`
+    pub max_frame_max:     Option<u32>,
+    /// The `connection_name` client property, displayed by the broker to identify the connection, none is sent if `None`
+    ///
+    /// When connecting using a `&str`, it defaults to the `connection_name` query parameter.
+    /// A `connection_name` already present in the `ConnectionProperties` takes precedence.
+    pub connection_name:   Option<String>,
+    /// The locale to request in the `connection.start-ok`, taking precedence over the `ConnectionProperties` if set
+    ///
+    /// When connecting using a `&str`, it defaults to the `locale` query parameter. lapin only logs
+    /// an error if the broker doesn't advertise it, the broker then picks the locale it uses.
+    pub locale:            Option<String>,
+    /// The `Resolver` used to resolve the uri host, defaults to `SystemResolver` if `None`
+    pub resolver:          Option<Arc<dyn Resolver>>,
+    /// The channel to which the `ConnectionEvent`s are sent while connecting, if any
+    pub events:            Option<UnboundedSender<ConnectionEvent>>,
+}
+
+impl Default for AMQPStreamOptions {
+    fn default() -> AMQPStreamOptions {
+        AMQPStreamOptions {
+            connect_timeout:   None,
+            handshake_timeout: None,
+            addr:              None,
+            proxy:             None,
+            http_proxy:        None,
+            proxy_from_env:    false,
+            server_name:       None,
+            verify_hostname:   true,
+            proxy_protocol:    None,
+            nodelay:           true,
+            keepalive:         None,
+            local_addr:        None,
+            ip_family:         None,
+            #[cfg(target_os = "linux")]
+            bind_device:       None,
+            recv_buffer_size:  None,
+            send_buffer_size:  None,
+            heartbeat:         None,
+            max_frame_max:     None,
+            connection_name:   None,
+            locale:            None,
+            resolver:          None,
+            events:            None,
+        }
+    }
+}
+
+impl AMQPStreamOptions {
+    fn notify(&self, event: ConnectionEvent) {
+        if let Some(ref events) = self.events {
+            // The receiver not listening anymore shouldn't make the connection fail
+            let _ = events.unbounded_send(event);
+        }
+    }
+}
+
+/// An IP family to restrict the connection to, see `AMQPStreamOptions::ip_family`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[deprecated(note = "use lapin directly instead")]
+pub enum IpFamily {
+    /// Only connect over IPv4
+    V4,
+    /// Only connect over IPv6
+    V6,
+}
+
+/// The steps reached while establishing a connection, see `AMQPStreamOptions::events`.
+#[derive(Clone, Debug)]
+#[deprecated(note = "use lapin directly instead")]
+pub enum ConnectionEvent {
+    /// The `TcpStream` is connected to the given address
+    TcpConnected(SocketAddr),
+    /// The TLS handshake has completed, taking the given time from the connected `TcpStream`
+    TlsHandshakeCompleted(Duration),
+    /// The AMQP connection has been negotiated with the broker, using the given parameters
+    Negotiated(ConnectionConfiguration),
 }
 
 /// Add a connect method providing a `lapin_futures::client::Client` wrapped in a `Future`.
+///
+/// Dropping the `Future` before it completes aborts the connection, closing the sockets opened so far.
 #[deprecated(note = "use lapin directly instead")]
 pub trait AMQPConnectionTlsExt<TlsStream: AsyncRead + AsyncWrite + Send + Sync + 'static> {
     /// Method providing a `lapin_futures::client::Client`, a `lapin_futures::client::HeartbeatHandle` and a `lapin::client::Heartbeat` pulse wrapped in a `Future`
-    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static>;
+    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> ConnectFuture<TlsStream>;
     /// Method providing a `lapin_futures::client::Client` and `lapin_futures::client::HeartbeatHandle` wrapped in a `Future`
     fn connect_cancellable<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static>;
     /// Method providing a `lapin_futures::client::Client`, a `lapin_futures::client::HeartbeatHandle` and a `lapin::client::Heartbeat` pulse wrapped in a `Future`
-    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static>;
+    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> ConnectFuture<TlsStream>;
     /// Method providing a `lapin_futures::client::Client` and `lapin_futures::client::HeartbeatHandle` wrapped in a `Future`
     fn connect_cancellable_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static>;
+    /// Method providing a `lapin_futures::client::Client`, a `lapin_futures::client::HeartbeatHandle` and a `lapin::client::Heartbeat` pulse wrapped in a `Future`, opening the underlying `TcpStream` according to the given `AMQPStreamOptions`
+    fn connect_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream>;
+    /// Method providing a `lapin_futures::client::Client` and `lapin_futures::client::HeartbeatHandle` wrapped in a `Future`, opening the underlying `TcpStream` according to the given `AMQPStreamOptions`
+    fn connect_cancellable_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static>;
+    /// Method providing a `lapin_futures::client::Client`, a `lapin_futures::client::HeartbeatHandle` and a `lapin::client::Heartbeat` pulse wrapped in a `Future`, over an already connected `TcpStream`
+    ///
+    /// Only the TLS and AMQP related `AMQPStreamOptions` are used, the uri host is still used as the TLS server name unless `server_name` is set.
+    fn connect_with_tcp_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, stream: TcpStream, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream>;
+    /// Method providing a `lapin_futures::client::Client`, a `lapin_futures::client::HeartbeatHandle` and a `lapin::client::Heartbeat` pulse wrapped in a `Future`, over the Unix domain socket at `path`
+    ///
+    /// The uri scheme, host and port are ignored, the connection is never encrypted.
+    #[cfg(unix)]
+    fn connect_uds(self, path: PathBuf, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream>;
+    /// Method providing the `AMQPStream` connected to the broker wrapped in a `Future`, stopping after the TLS handshake without negotiating the AMQP connection
+    ///
+    /// The `handshake_timeout` of the `AMQPStreamOptions` only covers the TLS handshake.
+    fn connect_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, options: AMQPStreamOptions) -> Box<dyn Future<Item = AMQPStream<TlsStream>, Error = Error> + Send + 'static>;
 }
 
 impl<TlsStream: AsyncRead + AsyncWrite + Send + Sync + 'static> AMQPConnectionTlsExt<TlsStream> for AMQPUri {
-    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static> {
+    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> ConnectFuture<TlsStream> {
         self.connect_full(connector, ConnectionProperties::default())
     }
 
@@ -112,71 +328,220 @@ impl<TlsStream: AsyncRead + AsyncWrite + Send + Sync + 'static> AMQPConnectionTl
         self.connect_cancellable_full(heartbeat_error_handler, connector, ConnectionProperties::default())
     }
 
-    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static> {
-        Box::new(AMQPStream::from_amqp_uri(&self, connector).and_then(move |stream| lapin::client::Client::connect(stream, ConnectionOptions::from_uri(self, properties)).map(|(client, mut heartbeat)| (client, heartbeat.handle().unwrap(), Box::new(heartbeat.map_err(|e| ErrorKind::ProtocolError(e).into())) as Box<dyn Future<Item = (), Error = Error> + Send + 'static>)).map_err(|e| ErrorKind::ProtocolError(e).into())))
+    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> ConnectFuture<TlsStream> {
+        self.connect_with_options(connector, properties, AMQPStreamOptions::default())
     }
 
     fn connect_cancellable_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static> {
-        Box::new(self.connect_full(connector, properties).map(move |(client, heartbeat_handle, heartbeat_future)| {
+        self.connect_cancellable_with_options(heartbeat_error_handler, connector, properties, AMQPStreamOptions::default())
+    }
+
+    fn connect_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        let id = next_connection_id();
+        Box::new(open_tcp_stream(id, self.authority.host.clone(), self.authority.port, &options).and_then(move |stream| {
+            let handshake = AMQPStream::handshake(id, &self.scheme, self.authority.host.clone(), stream, &options, connector);
+            negotiate(self, id, handshake, properties, options)
+        }))
+    }
+
+    fn connect_cancellable_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static> {
+        Box::new(self.connect_with_options(connector, properties, options).map(move |(client, heartbeat_handle, heartbeat_future)| {
             tokio_executor::spawn(heartbeat_future.map_err(|e| heartbeat_error_handler(e)));
             (client, heartbeat_handle)
         }))
     }
+
+    fn connect_with_tcp_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, stream: TcpStream, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        let id = next_connection_id();
+        debug!("connection {}: using the given TcpStream", id);
+        let handshake = AMQPStream::handshake(id, &self.scheme, self.authority.host.clone(), stream, &options, connector);
+        negotiate(self, id, handshake, properties, options)
+    }
+
+    #[cfg(unix)]
+    fn connect_uds(self, path: PathBuf, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        let id              = next_connection_id();
+        let connect_timeout = options.connect_timeout;
+        debug!("connection {}: connecting to {}", id, path.display());
+        let connect         = UnixStream::connect(&path);
+        Box::new(match connect_timeout {
+            Some(timeout) => futures::future::Either::A(Timeout::new(connect, timeout).map_err(|e| timeout_error(e, "Timed out while connecting"))),
+            None          => futures::future::Either::B(connect),
+        }.map_err(|e| ErrorKind::ConnectionFailed(e).into()).and_then(move |stream| {
+            debug!("connection {}: connected to {}", id, path.display());
+            negotiate(self, id, Box::new(futures::future::ok(AMQPStream::Uds(stream))), properties, options)
+        }))
+    }
+
+    fn connect_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, options: AMQPStreamOptions) -> Box<dyn Future<Item = AMQPStream<TlsStream>, Error = Error> + Send + 'static> {
+        let id                = next_connection_id();
+        let handshake_timeout = options.handshake_timeout;
+        Box::new(open_tcp_stream(id, self.authority.host.clone(), self.authority.port, &options).and_then(move |stream| {
+            let handshake = AMQPStream::handshake(id, &self.scheme, self.authority.host, stream, &options, connector);
+            match handshake_timeout {
+                Some(timeout) => futures::future::Either::A(Timeout::new(handshake, timeout).map_err(handshake_timeout_error)),
+                None          => futures::future::Either::B(handshake),
+            }
+        }))
+    }
 }
 
 macro_rules! try_uri (
     ($self: expr) => ({
-        match $self.parse::<AMQPUri>() {
+        match query::check_scheme($self).and_then(|()| $self.parse::<AMQPUri>()) {
             Ok(uri) => uri,
             Err(err) => return Box::new(futures::future::err(ErrorKind::UriParsingError(err).into())),
         }
     });
 );
 
+macro_rules! try_query (
+    ($self: expr, $options: expr) => ({
+        match $options.with_query($self) {
+            Ok(options) => options,
+            Err(err)    => return Box::new(futures::future::err(ErrorKind::UriParsingError(err).into())),
+        }
+    });
+);
+
 impl<'a, TlsStream: AsyncRead + AsyncWrite + Send + Sync + 'static> AMQPConnectionTlsExt<TlsStream> for &'a str {
-    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static> {
-        try_uri!(self).connect(connector)
+    fn connect<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector) -> ConnectFuture<TlsStream> {
+        self.connect_full(connector, ConnectionProperties::default())
     }
 
     fn connect_cancellable<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static> {
-        try_uri!(self).connect_cancellable(heartbeat_error_handler, connector)
+        self.connect_cancellable_full(heartbeat_error_handler, connector, ConnectionProperties::default())
     }
 
-    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle, Box<dyn Future<Item = (), Error = Error> + Send + 'static>), Error = Error> + Send + 'static> {
-        try_uri!(self).connect_full(connector, properties)
+    fn connect_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties) -> ConnectFuture<TlsStream> {
+        self.connect_with_options(connector, properties, AMQPStreamOptions::default())
     }
 
     fn connect_cancellable_full<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static> {
-        try_uri!(self).connect_cancellable_full(heartbeat_error_handler, connector, properties)
+        self.connect_cancellable_with_options(heartbeat_error_handler, connector, properties, AMQPStreamOptions::default())
+    }
+
+    fn connect_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        try_uri!(self).connect_with_options(connector, properties, try_query!(self, options))
+    }
+
+    fn connect_cancellable_with_options<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static, F: FnOnce(Error) + Send + 'static>(self, heartbeat_error_handler: F, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> Box<dyn Future<Item = (lapin::client::Client<AMQPStream<TlsStream>>, lapin::client::HeartbeatHandle), Error = Error> + Send + 'static> {
+        try_uri!(self).connect_cancellable_with_options(heartbeat_error_handler, connector, properties, try_query!(self, options))
+    }
+
+    fn connect_with_tcp_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, stream: TcpStream, connector: Connector, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        try_uri!(self).connect_with_tcp_stream(stream, connector, properties, try_query!(self, options))
+    }
+
+    #[cfg(unix)]
+    fn connect_uds(self, path: PathBuf, properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+        try_uri!(self).connect_uds(path, properties, try_query!(self, options))
+    }
+
+    fn connect_stream<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(self, connector: Connector, options: AMQPStreamOptions) -> Box<dyn Future<Item = AMQPStream<TlsStream>, Error = Error> + Send + 'static> {
+        try_uri!(self).connect_stream(connector, try_query!(self, options))
     }
 }
 
 impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> AMQPStream<TlsStream> {
-    fn from_amqp_uri<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(uri: &AMQPUri, connector: Connector) -> Box<dyn Future<Item = Self, Error = Error> + Send + 'static> {
-        match uri.scheme {
-            AMQPScheme::AMQP  => AMQPStream::raw(uri.authority.host.clone(), uri.authority.port),
-            AMQPScheme::AMQPS => AMQPStream::tls(uri.authority.host.clone(), uri.authority.port, connector),
+    /// Whether the connection is encrypted, `false` for raw connections
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn is_tls(&self) -> bool {
+        match *self {
+            AMQPStream::Raw(_) => false,
+            AMQPStream::Tls(_) => true,
+            #[cfg(unix)]
+            AMQPStream::Uds(_) => false,
+        }
+    }
+
+    /// Get a reference to the underlying `TlsStream`, `None` for raw connections
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn tls_stream(&self) -> Option<&TlsStream> {
+        match *self {
+            AMQPStream::Raw(_)       => None,
+            AMQPStream::Tls(ref tls) => Some(tls),
+            #[cfg(unix)]
+            AMQPStream::Uds(_)       => None,
+        }
+    }
+
+    /// Shut down the write half of the connection, signaling the end of the stream to the peer while still reading from it
+    ///
+    /// `AsyncWrite::shutdown` leaves the socket open for raw connections. A TLS connection can't be half-closed
+    /// this way as the TLS session needs to write its `close_notify`, an error is returned, use `AsyncWrite::shutdown` instead.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn shutdown_write(&self) -> io::Result<()> {
+        match *self {
+            AMQPStream::Raw(ref raw) => raw.shutdown(net::Shutdown::Write),
+            AMQPStream::Tls(_)       => Err(io::Error::new(io::ErrorKind::Other, "A TLS connection can't be half-closed, use AsyncWrite::shutdown")),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref uds) => uds.shutdown(net::Shutdown::Write),
         }
     }
 
-    fn raw(host: String, port: u16) -> Box<dyn Future<Item = Self, Error = Error> + Send + 'static> {
-        Box::new(open_tcp_stream(host, port).map(AMQPStream::Raw))
+    fn handshake<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(id: usize, scheme: &AMQPScheme, host: String, stream: TcpStream, options: &AMQPStreamOptions, connector: Connector) -> Box<dyn Future<Item = Self, Error = Error> + Send + 'static> {
+        match *scheme {
+            AMQPScheme::AMQP  => Box::new(futures::future::ok(AMQPStream::Raw(stream))),
+            AMQPScheme::AMQPS => AMQPStream::tls(id, host, stream, options, connector),
+        }
     }
 
-    fn tls<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(host: String, port: u16, connector: Connector) -> Box<dyn Future<Item = Self, Error = Error> + Send + 'static> {
+    fn tls<Connector: FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream>, Error = io::Error> + Send + 'static> + Send + 'static>(id: usize, host: String, stream: TcpStream, options: &AMQPStreamOptions, connector: Connector) -> Box<dyn Future<Item = Self, Error = Error> + Send + 'static> {
+        if !options.verify_hostname {
+            return Box::new(futures::future::err(ErrorKind::TlsHandshakeFailed(io::Error::new(io::ErrorKind::InvalidInput, "verify_hostname=false isn't supported by this TLS connector")).into()));
+        }
+        let options = options.clone();
+        let host    = options.server_name.clone().unwrap_or(host);
+        debug!("connection {}: starting TLS handshake with {}", id, host);
+        let start   = Instant::now();
         Box::new(
-            open_tcp_stream(host.clone(), port).and_then(move |stream| {
-                connector(host, stream).map(AMQPStream::Tls).map_err(|e| ErrorKind::ConnectionFailed(e).into())
-            })
+            connector(host, stream).map(move |stream| {
+                let duration = start.elapsed();
+                debug!("connection {}: TLS handshake completed in {:?}", id, duration);
+                options.notify(ConnectionEvent::TlsHandshakeCompleted(duration));
+                AMQPStream::Tls(stream)
+            }).map_err(|e| ErrorKind::TlsHandshakeFailed(e).into())
         )
     }
 }
 
+impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> fmt::Debug for AMQPStream<TlsStream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AMQPStream::Raw(ref raw) => f.debug_struct("Raw").field("peer_addr", &raw.peer_addr().ok()).finish(),
+            // Don't print anything from the TLS session to avoid leaking any secret
+            AMQPStream::Tls(_)       => f.debug_struct("Tls").finish(),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref uds) => f.debug_struct("Uds").field("peer_addr", &uds.peer_addr().ok()).finish(),
+        }
+    }
+}
+
+impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> fmt::Display for AMQPStream<TlsStream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AMQPStream::Raw(ref raw) => match raw.peer_addr() {
+                Ok(peer_addr) => write!(f, "TCP connection to {}", peer_addr),
+                Err(_)        => f.write_str("TCP connection"),
+            },
+            AMQPStream::Tls(_)       => f.write_str("TLS connection"),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref uds) => match uds.peer_addr().ok().and_then(|addr| addr.as_pathname().map(|path| path.to_path_buf())) {
+                Some(path) => write!(f, "Unix socket connection to {}", path.display()),
+                None       => f.write_str("Unix socket connection"),
+            },
+        }
+    }
+}
+
 impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> Read for AMQPStream<TlsStream> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match *self {
             AMQPStream::Raw(ref mut raw) => raw.read(buf),
             AMQPStream::Tls(ref mut tls) => tls.read(buf),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => uds.read(buf),
         }
     }
 }
@@ -186,13 +551,17 @@ impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> AsyncRead for AMQPStrea
         match *self {
             AMQPStream::Raw(ref raw) => raw.prepare_uninitialized_buffer(buf),
             AMQPStream::Tls(ref tls) => tls.prepare_uninitialized_buffer(buf),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref uds) => uds.prepare_uninitialized_buffer(buf),
         }
     }
 
     fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
         match *self {
-            AMQPStream::Raw(ref mut raw) => raw.read_buf(buf),
-            AMQPStream::Tls(ref mut tls) => tls.read_buf(buf),
+            AMQPStream::Raw(ref mut raw) => AsyncRead::read_buf(raw, buf),
+            AMQPStream::Tls(ref mut tls) => AsyncRead::read_buf(tls, buf),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => AsyncRead::read_buf(uds, buf),
         }
     }
 }
@@ -202,6 +571,8 @@ impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> Write for AMQPStream<Tl
         match *self {
             AMQPStream::Raw(ref mut raw) => raw.write(buf),
             AMQPStream::Tls(ref mut tls) => tls.write(buf),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => uds.write(buf),
         }
     }
 
@@ -209,6 +580,8 @@ impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> Write for AMQPStream<Tl
         match *self {
             AMQPStream::Raw(ref mut raw) => raw.flush(),
             AMQPStream::Tls(ref mut tls) => tls.flush(),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => uds.flush(),
         }
     }
 }
@@ -218,27 +591,377 @@ impl<TlsStream: AsyncRead + AsyncWrite + Send + 'static> AsyncWrite for AMQPStre
         match *self {
             AMQPStream::Raw(ref mut raw) => raw.shutdown(),
             AMQPStream::Tls(ref mut tls) => tls.shutdown(),
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => AsyncWrite::shutdown(uds),
         }
     }
 
     fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
         match *self {
             AMQPStream::Raw(ref mut raw) => raw.write_buf(buf),
-            AMQPStream::Tls(ref mut tls) => tls.write_buf(buf),
+            AMQPStream::Tls(ref mut tls) => {
+                if buf.bytes().len() < buf.remaining() {
+                    write_coalesced(tls, buf)
+                } else {
+                    tls.write_buf(buf)
+                }
+            },
+            #[cfg(unix)]
+            AMQPStream::Uds(ref mut uds) => uds.write_buf(buf),
+        }
+    }
+}
+
+/// The maximum amount of plaintext a single TLS record can hold
+const MAX_TLS_PLAINTEXT: usize = 16 * 1024;
+
+/// Copy the chunks of `buf` into a single buffer before writing them, so that they can be
+/// encrypted as one TLS record instead of one per chunk
+fn write_coalesced<W: Write, B: Buf>(writer: &mut W, buf: &mut B) -> Poll<usize, io::Error> {
+    let mut plaintext = Vec::with_capacity(buf.remaining().min(MAX_TLS_PLAINTEXT));
+    {
+        let mut chunks: [&IoVec; 64] = [Default::default(); 64];
+        let count = buf.bytes_vec(&mut chunks);
+        for chunk in chunks[..count].iter() {
+            let len = chunk.len().min(MAX_TLS_PLAINTEXT - plaintext.len());
+            plaintext.extend_from_slice(&chunk[..len]);
+            if plaintext.len() == MAX_TLS_PLAINTEXT {
+                break;
+            }
+        }
+    }
+    match writer.write(&plaintext) {
+        Ok(written)                                        => {
+            buf.advance(written);
+            Ok(Async::Ready(written))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+        Err(e)                                             => Err(e),
+    }
+}
+
+/// Negotiate the AMQP connection over the `AMQPStream` provided by `transport`, which may still be doing the TLS handshake
+fn negotiate<TlsStream: AsyncRead + AsyncWrite + Send + Sync + 'static>(mut uri: AMQPUri, id: usize, transport: Box<dyn Future<Item = AMQPStream<TlsStream>, Error = Error> + Send + 'static>, mut properties: ConnectionProperties, options: AMQPStreamOptions) -> ConnectFuture<TlsStream> {
+    let handshake_timeout = options.handshake_timeout;
+    if let Some(ref name) = options.connection_name {
+        properties.client_properties.entry("connection_name".to_string()).or_insert_with(|| AMQPValue::LongString(name.clone()));
+    }
+    if let Some(ref locale) = options.locale {
+        properties.locale = locale.clone();
+    }
+    if let Some(heartbeat) = options.heartbeat {
+        uri.query.heartbeat = Some(heartbeat);
+    }
+    if let Some(max_frame_max) = options.max_frame_max {
+        // lapin negotiates the lowest non-zero value between ours and the broker's, 0 meaning no limit
+        uri.query.frame_max = Some(match uri.query.frame_max {
+            Some(frame_max) if frame_max != 0 => frame_max.min(max_frame_max),
+            _                                 => max_frame_max,
+        });
+    }
+    let handshake = transport.and_then(move |stream| lapin::client::Client::connect(stream, ConnectionOptions::from_uri(uri, properties)).map(move |(client, heartbeat)| {
+        debug!("connection {}: AMQP connection negotiated, channel_max={} frame_max={} heartbeat={}", id, client.configuration.channel_max, client.configuration.frame_max, client.configuration.heartbeat);
+        options.notify(ConnectionEvent::Negotiated(client.configuration.clone()));
+        (client, heartbeat)
+    }).map(|(client, mut heartbeat)| (client, heartbeat.handle().unwrap(), Box::new(heartbeat.map_err(|e| ErrorKind::ProtocolError(e).into())) as Box<dyn Future<Item = (), Error = Error> + Send + 'static>)).map_err(|e| ErrorKind::ProtocolError(e).into()));
+    match handshake_timeout {
+        Some(timeout) => Box::new(Timeout::new(handshake, timeout).map_err(handshake_timeout_error)),
+        None          => Box::new(handshake),
+    }
+}
+
+fn open_tcp_stream(id: usize, host: String, port: u16, options: &AMQPStreamOptions) -> Box<dyn Future<Item = TcpStream, Error = Error> + Send + 'static> {
+    let connect_timeout = options.connect_timeout;
+    let socket_options  = options.clone();
+    let events_options  = options.clone();
+    let proxy           = match proxy(id, &host, port, options) {
+        Ok(proxy) => proxy,
+        Err(err)  => return Box::new(futures::future::err(ErrorKind::ConnectionFailed(err).into())),
+    };
+    let proxy_target: Box<dyn Future<Item = Option<(ProxyKind, String, u16)>, Error = Error> + Send + 'static> = match (proxy.as_ref().map(|&(kind, _)| kind), options.addr) {
+        (Some(kind), Some(addr))        => Box::new(futures::future::ok(Some((kind, addr.ip().to_string(), addr.port())))),
+        // socks5:// asks for the broker host to be resolved locally, only its address being sent to the proxy
+        (Some(ProxyKind::Socks5), None) => {
+            debug!("connection {}: resolving {} for the SOCKS5 proxy", id, host);
+            let ip_family = options.ip_family;
+            Box::new(resolve_host(options.resolver.clone(), host.clone(), port).and_then(move |addrs| filter_ip_family(addrs, ip_family)).map(move |addrs| Some((ProxyKind::Socks5, addrs[0].ip().to_string(), port))))
+        },
+        (Some(kind), None)              => Box::new(futures::future::ok(Some((kind, host.clone(), port)))),
+        (None, _)                       => Box::new(futures::future::ok(None)),
+    };
+    let addrs           = match (proxy, options.addr) {
+        (Some((_, addrs)), _) => addrs,
+        (None, Some(addr))    => Box::new(futures::future::ok(vec![addr])),
+        (None, None)          => {
+            debug!("connection {}: resolving {}", id, host);
+            resolve_host(options.resolver.clone(), host, port)
+        },
+    };
+    let proxy_protocol  = options.proxy_protocol;
+    let ip_family       = options.ip_family;
+    Box::new(
+        addrs.join(proxy_target).and_then(move |(addrs, proxy_target)| filter_ip_family(addrs, ip_family).map(|addrs| (addrs, proxy_target))).and_then(move |(addrs, proxy_target)| {
+            let connect = HappyEyeballs::new(addrs, move |addr| connect_socket(addr, &socket_options));
+            let connect: Box<dyn Future<Item = TcpStream, Error = io::Error> + Send + 'static> = match proxy_target {
+                Some((ProxyKind::Socks5, host, port))      |
+                Some((ProxyKind::Socks5h, host, port))     => Box::new(connect.and_then(move |stream| socks::connect(stream, host, port))),
+                Some((ProxyKind::HttpConnect, host, port)) => Box::new(connect.and_then(move |stream| http_connect::connect(stream, host, port))),
+                None                                       => Box::new(connect),
+            };
+            let connect: Box<dyn Future<Item = TcpStream, Error = io::Error> + Send + 'static> = match proxy_protocol {
+                Some((source, destination)) => Box::new(connect.and_then(move |stream| proxy_protocol::send_header(stream, source, destination))),
+                None                        => connect,
+            };
+            match connect_timeout {
+                Some(timeout) => futures::future::Either::A(Timeout::new(connect, timeout).map_err(|e| timeout_error(e, "Timed out while connecting"))),
+                None          => futures::future::Either::B(connect),
+            }.and_then(move |stream| {
+                let peer_addr = stream.peer_addr()?;
+                debug!("connection {}: TCP connected, local={} peer={}", id, stream.local_addr()?, peer_addr);
+                events_options.notify(ConnectionEvent::TcpConnected(peer_addr));
+                Ok(stream)
+            }).map_err(|e| ErrorKind::ConnectionFailed(e).into())
+        })
+    )
+}
+
+fn filter_ip_family(addrs: Vec<SocketAddr>, ip_family: Option<IpFamily>) -> Result<Vec<SocketAddr>, Error> {
+    match ip_family {
+        Some(family) => {
+            let addrs = addrs.into_iter().filter(|addr| addr.is_ipv4() == (family == IpFamily::V4)).collect::<Vec<_>>();
+            if addrs.is_empty() {
+                let family = match family {
+                    IpFamily::V4 => "IPv4",
+                    IpFamily::V6 => "IPv6",
+                };
+                Err(ErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::AddrNotAvailable, format!("No {} address to connect to", family))).into())
+            } else {
+                Ok(addrs)
+            }
+        },
+        None         => Ok(addrs),
+    }
+}
+
+/// The kind of proxy to go through to reach `host:port` and the future resolving its addresses, if any
+fn proxy(id: usize, host: &str, port: u16, options: &AMQPStreamOptions) -> io::Result<Option<(ProxyKind, ResolveFuture)>> {
+    if let Some(proxy) = options.proxy {
+        return Ok(Some((ProxyKind::Socks5h, Box::new(futures::future::ok(vec![proxy])))));
+    }
+    if let Some(proxy) = options.http_proxy {
+        return Ok(Some((ProxyKind::HttpConnect, Box::new(futures::future::ok(vec![proxy])))));
+    }
+    if !options.proxy_from_env {
+        return Ok(None);
+    }
+    Ok(proxy_env::lookup(host, port)?.map(|proxy| {
+        debug!("connection {}: using the {:?} proxy {}:{} from the environment", id, proxy.kind, proxy.host, proxy.port);
+        (proxy.kind, resolve_host(options.resolver.clone(), proxy.host, proxy.port))
+    }))
+}
+
+static CONNECTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A unique identifier to correlate the log lines of a connection
+fn next_connection_id() -> usize {
+    CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn connect_socket(addr: SocketAddr, options: &AMQPStreamOptions) -> Box<dyn Future<Item = TcpStream, Error = io::Error> + Send + 'static> {
+    let options = options.clone();
+    Box::new(
+        if needs_socket_builder(&options) {
+            futures::future::Either::A(futures::future::result(build_socket(&addr, &options)).and_then(move |socket| TcpStream::connect_std(socket, &addr, &Handle::default())))
+        } else {
+            futures::future::Either::B(TcpStream::connect(&addr))
+        }.and_then(move |stream| configure_socket(&stream, &options).map(|_| stream))
+    )
+}
+
+/// Whether some options have to be applied before connecting, requiring `build_socket`
+fn needs_socket_builder(options: &AMQPStreamOptions) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        if options.bind_device.is_some() {
+            return true;
+        }
+    }
+    options.local_addr.is_some() || options.recv_buffer_size.is_some() || options.send_buffer_size.is_some()
+}
+
+/// Create the socket ourselves for the options which have to be applied before connecting
+fn build_socket(addr: &SocketAddr, options: &AMQPStreamOptions) -> io::Result<net::TcpStream> {
+    let builder = match *addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(ref device) = options.bind_device {
+            bind_to_device(&builder, device)?;
         }
     }
+    if let Some(ref local_addr) = options.local_addr {
+        builder.bind(local_addr)?;
+    }
+    let socket = builder.to_tcp_stream()?;
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_device<S: AsRawFd>(socket: &S, device: &str) -> io::Result<()> {
+    let res = unsafe { libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BINDTODEVICE, device.as_ptr() as *const libc::c_void, device.len() as libc::socklen_t) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
 }
 
-fn open_tcp_stream(host: String, port: u16) -> Box<dyn Future<Item = TcpStream, Error = Error> + Send + 'static> {
-    let host2 = host.clone();
+fn configure_socket(stream: &TcpStream, options: &AMQPStreamOptions) -> io::Result<()> {
+    stream.set_nodelay(options.nodelay)?;
+    stream.set_keepalive(options.keepalive)
+}
+
+fn resolve_host(resolver: Option<Arc<dyn Resolver>>, host: String, port: u16) -> ResolveFuture {
+    let resolver = resolver.unwrap_or_else(|| Arc::new(SystemResolver));
     Box::new(
-        futures::future::result(AsyncResolver::from_system_conf()).and_then(move |(resolver, background)| {
-            tokio_executor::spawn(background);
-            resolver.lookup_ip(host.as_str())
-        }).map_err(|e| ErrorKind::InvalidDomainName(e.to_string()).into()).and_then(|response| {
-            response.iter().next().ok_or_else(|| ErrorKind::InvalidDomainName(host2).into())
-        }).and_then(move |ipaddr| {
-            TcpStream::connect(&SocketAddr::new(ipaddr, port)).map_err(|e| ErrorKind::ConnectionFailed(e).into())
+        resolver.resolve(&host, port).map_err(|e| ErrorKind::InvalidDomainName(e.to_string()).into()).and_then(move |addrs| {
+            if addrs.is_empty() {
+                Err(ErrorKind::InvalidDomainName(host).into())
+            } else {
+                Ok(addrs)
+            }
         })
     )
 }
+
+fn handshake_timeout_error(err: timeout::Error<Error>) -> Error {
+    if err.is_elapsed() {
+        ErrorKind::HandshakeTimedOut.into()
+    } else if err.is_timer() {
+        ErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::Other, format!("Timer error: {}", err))).into()
+    } else {
+        err.into_inner().unwrap_or_else(|| ErrorKind::HandshakeTimedOut.into())
+    }
+}
+
+fn timeout_error(err: timeout::Error<io::Error>, message: &str) -> io::Error {
+    if err.is_elapsed() {
+        io::Error::new(io::ErrorKind::TimedOut, message)
+    } else if err.is_timer() {
+        io::Error::new(io::ErrorKind::Other, format!("Timer error: {}", err))
+    } else {
+        err.into_inner().unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[cfg(target_os = "linux")]
+    fn bound_device(stream: &TcpStream) -> String {
+        let mut device = [0u8; 16];
+        let mut len    = device.len() as libc::socklen_t;
+        let res        = unsafe { libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BINDTODEVICE, device.as_mut_ptr() as *mut libc::c_void, &mut len) };
+        assert_eq!(res, 0, "getsockopt failed: {}", io::Error::last_os_error());
+        String::from_utf8_lossy(&device[..len as usize]).trim_end_matches('\0').to_string()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bind_device_alone_is_applied() {
+        use std::net::{Ipv4Addr, TcpListener};
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr     = listener.local_addr().unwrap();
+        let options  = AMQPStreamOptions { bind_device: Some("lo".to_string()), ..AMQPStreamOptions::default() };
+        let stream   = tokio::runtime::current_thread::block_on_all(connect_socket(addr, &options)).unwrap();
+        assert_eq!(bound_device(&stream), "lo");
+
+        let options = AMQPStreamOptions { bind_device: Some("nonexistent0".to_string()), ..AMQPStreamOptions::default() };
+        assert!(tokio::runtime::current_thread::block_on_all(connect_socket(addr, &options)).is_err());
+    }
+
+    /// Resolve every host name to 192.0.2.7
+    #[derive(Debug)]
+    struct FixedResolver;
+
+    impl Resolver for FixedResolver {
+        fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static> {
+            let ip = host.parse().unwrap_or_else(|_| [192, 0, 2, 7].into());
+            Box::new(futures::future::ok(vec![SocketAddr::new(ip, port)]))
+        }
+    }
+
+    /// Resolve every host name to 2001:db8::7 first, then to 192.0.2.7
+    #[derive(Debug)]
+    struct DualStackResolver;
+
+    impl Resolver for DualStackResolver {
+        fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static> {
+            let addrs = match host.parse() {
+                Ok(ip) => vec![SocketAddr::new(ip, port)],
+                Err(_) => vec![SocketAddr::new([0x2001, 0xdb8, 0, 0, 0, 0, 0, 7].into(), port), SocketAddr::new([192, 0, 2, 7].into(), port)],
+            };
+            Box::new(futures::future::ok(addrs))
+        }
+    }
+
+    /// Connect to `broker:5672` using the proxy from `ALL_PROXY`, returning the SOCKS5 request received by the proxy
+    fn socks_request(scheme: &str, options: AMQPStreamOptions) -> Vec<u8> {
+        use std::io::{Read, Write};
+        use std::net::{Ipv4Addr, TcpListener};
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        env::set_var("ALL_PROXY", format!("{}://{}", scheme, listener.local_addr().unwrap()));
+        let proxy    = std::thread::spawn(move || {
+            let mut stream   = listener.accept().unwrap().0;
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = vec![0u8; 256];
+            let len         = stream.read(&mut request).unwrap();
+            request.truncate(len);
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            request
+        });
+        let options  = AMQPStreamOptions { proxy_from_env: true, ..options };
+        tokio::runtime::current_thread::block_on_all(open_tcp_stream(0, "broker".to_string(), 5672, &options)).unwrap();
+        proxy.join().unwrap()
+    }
+
+    // The only test touching the proxy environment variables, as they are shared by the whole process
+    #[test]
+    fn socks5_proxy_from_env() {
+        env::remove_var("all_proxy");
+        env::remove_var("https_proxy");
+        env::remove_var("HTTPS_PROXY");
+        env::remove_var("no_proxy");
+        env::remove_var("NO_PROXY");
+        let fixed = || AMQPStreamOptions { resolver: Some(Arc::new(FixedResolver)), ..AMQPStreamOptions::default() };
+        // socks5:// resolves the broker host locally
+        assert_eq!(socks_request("socks5", fixed()), vec![5, 1, 0, 1, 192, 0, 2, 7, 0x16, 0x28]);
+        // socks5h:// lets the proxy resolve it
+        assert_eq!(socks_request("socks5h", fixed()), [&[5, 1, 0, 3, 6][..], b"broker", &[0x16, 0x28]].concat());
+        // The locally resolved broker address honours the ip family
+        let dual_stack_v4 = AMQPStreamOptions { resolver: Some(Arc::new(DualStackResolver)), ip_family: Some(IpFamily::V4), ..AMQPStreamOptions::default() };
+        assert_eq!(socks_request("socks5", dual_stack_v4), vec![5, 1, 0, 1, 192, 0, 2, 7, 0x16, 0x28]);
+
+        env::set_var("NO_PROXY", "broker");
+        assert_eq!(proxy_env::lookup("broker", 5672).unwrap(), None);
+        assert!(proxy_env::lookup("other", 5672).unwrap().is_some());
+        env::remove_var("NO_PROXY");
+        env::remove_var("ALL_PROXY");
+        assert_eq!(proxy_env::lookup("broker", 5672).unwrap(), None);
+    }
+}
@@ -0,0 +1,79 @@
+use bytes::BufMut;
+use futures::future::Future;
+use tokio_io::io::write_all;
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::TcpStream;
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// Version 2, PROXY command
+const PROXY:     u8       = 0x21;
+const TCP_IPV4:  u8       = 0x11;
+const TCP_IPV6:  u8       = 0x21;
+
+/// Send the PROXY protocol v2 header announcing a connection from `source` to `destination` on `stream`
+pub(crate) fn send_header(stream: TcpStream, source: SocketAddr, destination: SocketAddr) -> impl Future<Item = TcpStream, Error = io::Error> + Send + 'static {
+    write_all(stream, header(source, destination)).map(|(stream, _)| stream)
+}
+
+fn header(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+    header.push(PROXY);
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            header.push(TCP_IPV4);
+            header.put_u16_be(12);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+        },
+        // Addresses of different families are both sent as IPv6, the IPv4 one being mapped
+        (src, dst)                         => {
+            header.push(TCP_IPV6);
+            header.put_u16_be(36);
+            header.extend_from_slice(&ipv6_octets(src));
+            header.extend_from_slice(&ipv6_octets(dst));
+        },
+    }
+    header.put_u16_be(source.port());
+    header.put_u16_be(destination.port());
+    header
+}
+
+fn ipv6_octets(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn encode_ipv4_header() {
+        let header = header("192.0.2.1:40000".parse().unwrap(), "198.51.100.2:5672".parse().unwrap());
+        assert_eq!(header, [&SIGNATURE[..], &[0x21, 0x11, 0, 12, 192, 0, 2, 1, 198, 51, 100, 2, 0x9c, 0x40, 0x16, 0x28]].concat());
+    }
+
+    #[test]
+    fn encode_ipv6_header() {
+        let header = header("[2001:db8::1]:40000".parse().unwrap(), "[2001:db8::2]:5671".parse().unwrap());
+        assert_eq!(&header[..16], &[&SIGNATURE[..], &[0x21, 0x21, 0, 36]].concat()[..]);
+        assert_eq!(&header[16..32], &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        assert_eq!(&header[32..48], &"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        assert_eq!(&header[48..], &[0x9c, 0x40, 0x16, 0x27]);
+    }
+
+    #[test]
+    fn encode_mixed_families_as_ipv6() {
+        let header = header("192.0.2.1:40000".parse().unwrap(), "[2001:db8::2]:5672".parse().unwrap());
+        assert_eq!(header.len(), 16 + 36);
+        assert_eq!(header[13], TCP_IPV6);
+        assert_eq!(&header[16..32], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 1]);
+    }
+}
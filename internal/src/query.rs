@@ -0,0 +1,192 @@
+use url::Url;
+
+use std::time::Duration;
+
+use crate::AMQPStreamOptions;
+
+const FRAME_MIN_SIZE: u32 = 4096;
+
+impl AMQPStreamOptions {
+    /// Fill the options which weren't explicitly set using the query parameters of `uri` unknown to `AMQPUri`
+    ///
+    /// This is done automatically when connecting using a `&str`. Schemes other than `amqp` and `amqps` are rejected.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn with_query(mut self, uri: &str) -> Result<AMQPStreamOptions, String> {
+        check_scheme(uri)?;
+        let url = Url::parse(uri).map_err(|e| e.to_string())?;
+        if self.connect_timeout.is_none() {
+            self.connect_timeout = parse_positive(&url, "connection_timeout")?.map(Duration::from_millis);
+        }
+        if self.server_name.is_none() {
+            self.server_name = query_param(&url, "server_name_indication");
+        }
+        if self.connection_name.is_none() {
+            self.connection_name = query_param(&url, "connection_name");
+        }
+        if self.locale.is_none() {
+            self.locale = query_param(&url, "locale");
+        }
+        self.verify_hostname = self.verify_hostname && parse_bool(&url, "verify_hostname")?.unwrap_or(true);
+        check_auth_mechanism(&url)?;
+        check_channel_max(&url)?;
+        check_frame_max(&url)?;
+        Ok(self)
+    }
+}
+
+/// Reject the schemes other than amqp and amqps with a more helpful message than the `AMQPUri` parser
+pub(crate) fn check_scheme(uri: &str) -> Result<(), String> {
+    match Url::parse(uri) {
+        Ok(ref url) if url.scheme() != "amqp" && url.scheme() != "amqps" => Err(format!("Unsupported scheme: '{}', expected 'amqp' or 'amqps'", url.scheme())),
+        // Let the AMQPUri parser report the other errors
+        _                                                                => Ok(()),
+    }
+}
+
+fn query_param(url: &Url, key: &str) -> Option<String> {
+    url.query_pairs().find(|(k, _)| k == key).map(|(_, value)| value.into_owned())
+}
+
+/// lapin-futures only supports the PLAIN SASL mechanism, reject the other ones instead of silently ignoring them
+fn check_auth_mechanism(url: &Url) -> Result<(), String> {
+    match query_param(url, "auth_mechanism") {
+        Some(ref mechanism) if !mechanism.eq_ignore_ascii_case("plain") => Err(format!("Unsupported auth_mechanism: '{}', only 'plain' is supported", mechanism)),
+        _                                                                 => Ok(()),
+    }
+}
+
+/// lapin-futures never sends a channel_max during the negotiation, reject it instead of silently letting the broker pick
+fn check_channel_max(url: &Url) -> Result<(), String> {
+    match query_param(url, "channel_max") {
+        Some(ref value) if value.parse::<u16>().map(|value| value > 0).unwrap_or(false) => Err(format!("Unsupported channel_max: '{}', the broker limit is always used", value)),
+        Some(value)                                                                      => Err(format!("Invalid channel_max: '{}', expected an integer between 1 and 65535", value)),
+        None                                                                             => Ok(()),
+    }
+}
+
+/// The broker closes the connection during the negotiation when asked for frames smaller than the AMQP minimum
+fn check_frame_max(url: &Url) -> Result<(), String> {
+    match query_param(url, "frame_max").map(|value| (value.parse::<u32>(), value)) {
+        Some((Ok(frame_max), ref value)) if frame_max != 0 && frame_max < FRAME_MIN_SIZE => Err(format!("Invalid frame_max: '{}', expected 0 or at least {}", value, FRAME_MIN_SIZE)),
+        // Let the AMQPUri parser report the values which aren't integers
+        _                                                                                 => Ok(()),
+    }
+}
+
+fn parse_bool(url: &Url, key: &str) -> Result<Option<bool>, String> {
+    match query_param(url, key) {
+        Some(value) => match value.as_str() {
+            "true"  => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            _       => Err(format!("Invalid {}: '{}', expected 'true' or 'false'", key, value)),
+        },
+        None        => Ok(None),
+    }
+}
+
+fn parse_positive(url: &Url, key: &str) -> Result<Option<u64>, String> {
+    match query_param(url, key) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(value) if value > 0 => Ok(Some(value)),
+            _                      => Err(format!("Invalid {}: '{}', expected a positive integer", key, value)),
+        },
+        None        => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(uri: &str) -> Result<AMQPStreamOptions, String> {
+        AMQPStreamOptions::default().with_query(uri)
+    }
+
+    #[test]
+    fn parse_query_params() {
+        let options = options("amqps://localhost/%2f?connection_timeout=1500&server_name_indication=broker.example&connection_name=worker&locale=fr_FR").unwrap();
+        assert_eq!(options.connect_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(options.server_name.as_ref().map(String::as_str), Some("broker.example"));
+        assert_eq!(options.connection_name.as_ref().map(String::as_str), Some("worker"));
+        assert_eq!(options.locale.as_ref().map(String::as_str), Some("fr_FR"));
+    }
+
+    #[test]
+    fn missing_query_params() {
+        let options = options("amqp://localhost/%2f").unwrap();
+        assert_eq!(options.connect_timeout, None);
+        assert_eq!(options.server_name, None);
+        assert_eq!(options.connection_name, None);
+        assert_eq!(options.locale, None);
+    }
+
+    #[test]
+    fn explicit_options_take_precedence() {
+        let options = AMQPStreamOptions { connect_timeout: Some(Duration::from_secs(3)), connection_name: Some("explicit".to_string()), ..AMQPStreamOptions::default() };
+        let options = options.with_query("amqp://localhost/%2f?connection_timeout=1500&connection_name=worker").unwrap();
+        assert_eq!(options.connect_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(options.connection_name.as_ref().map(String::as_str), Some("explicit"));
+    }
+
+    #[test]
+    fn percent_decode_query_params() {
+        let options = options("amqp://localhost/%2f?connection_name=my%20worker%26co").unwrap();
+        assert_eq!(options.connection_name.as_ref().map(String::as_str), Some("my worker&co"));
+    }
+
+    #[test]
+    fn reject_invalid_connection_timeout() {
+        assert!(options("amqp://localhost/%2f?connection_timeout=0").is_err());
+        assert!(options("amqp://localhost/%2f?connection_timeout=-1").is_err());
+        assert!(options("amqp://localhost/%2f?connection_timeout=soon").is_err());
+    }
+
+    #[test]
+    fn check_auth_mechanism_param() {
+        assert!(options("amqp://localhost/%2f?auth_mechanism=plain").is_ok());
+        assert!(options("amqp://localhost/%2f?auth_mechanism=PLAIN").is_ok());
+        assert!(options("amqp://localhost/%2f?auth_mechanism=external").is_err());
+    }
+
+    #[test]
+    fn parse_verify_hostname_param() {
+        assert!(options("amqps://localhost/%2f").unwrap().verify_hostname);
+        assert!(options("amqps://localhost/%2f?verify_hostname=true").unwrap().verify_hostname);
+        assert!(!options("amqps://localhost/%2f?verify_hostname=false").unwrap().verify_hostname);
+        assert!(options("amqps://localhost/%2f?verify_hostname=no").is_err());
+        let options = AMQPStreamOptions { verify_hostname: false, ..AMQPStreamOptions::default() };
+        assert!(!options.with_query("amqps://localhost/%2f?verify_hostname=true").unwrap().verify_hostname);
+    }
+
+    #[test]
+    fn check_channel_max_param() {
+        assert!(options("amqp://localhost/%2f?channel_max=0").is_err());
+        assert!(options("amqp://localhost/%2f?channel_max=2047").is_err());
+        assert!(options("amqp://localhost/%2f?channel_max=65536").is_err());
+    }
+
+    #[test]
+    fn check_frame_max_param() {
+        assert!(options("amqp://localhost/%2f?frame_max=0").is_ok());
+        assert!(options("amqp://localhost/%2f?frame_max=4096").is_ok());
+        assert!(options("amqp://localhost/%2f?frame_max=131072").is_ok());
+        assert!(options("amqp://localhost/%2f?frame_max=1").is_err());
+        assert!(options("amqp://localhost/%2f?frame_max=4095").is_err());
+        // Left to the AMQPUri parser
+        assert!(options("amqp://localhost/%2f?frame_max=big").is_ok());
+    }
+
+    #[test]
+    fn check_uri_scheme() {
+        assert_eq!(check_scheme("amqp://localhost/%2f"), Ok(()));
+        assert_eq!(check_scheme("amqps://localhost/%2f"), Ok(()));
+        assert!(check_scheme("http://localhost/%2f").is_err());
+        assert_eq!(check_scheme("not a uri"), Ok(()));
+    }
+
+    #[test]
+    fn reject_invalid_uri() {
+        assert!(options("not a uri").is_err());
+        assert!(options("http://localhost/%2f").unwrap_err().contains("Unsupported scheme"));
+    }
+}
@@ -0,0 +1,188 @@
+use futures::{self, future::Future};
+use trust_dns_resolver::{config::LookupIpStrategy, system_conf, AsyncResolver};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Resolve the host of the uri into the addresses to connect to.
+#[deprecated(note = "use lapin directly instead")]
+pub trait Resolver: fmt::Debug + Send + Sync {
+    /// Resolve `host` into the addresses to try when connecting to `port`, in order of preference
+    fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static>;
+}
+
+/// The default `Resolver`, an asynchronous resolver using the system configuration.
+#[derive(Clone, Debug, Default)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static> {
+        let host = host.to_string();
+        Box::new(
+            futures::future::result(system_conf::read_system_conf()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())).and_then(move |(config, mut options)| {
+                // Look both A and AAAA records up so that connection attempts can be raced over IPv4 and IPv6
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                let (resolver, background) = AsyncResolver::new(config, options);
+                tokio_executor::spawn(background);
+                resolver.lookup_ip(host.as_str()).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.to_string()))
+            }).map(move |response| response.iter().map(|ipaddr| SocketAddr::new(ipaddr, port)).collect())
+        )
+    }
+}
+
+/// The addresses of each host along with their expiration
+type Cache = HashMap<String, (Instant, Vec<IpAddr>)>;
+
+/// A `Resolver` caching the addresses found by another one for a fixed time.
+///
+/// Only successful lookups are cached. Expired entries are evicted when a new host is looked up, and the one
+/// closest to its expiration makes room for it once `max_entries` hosts are cached.
+#[derive(Clone, Debug)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct CachingResolver {
+    inner:       Arc<dyn Resolver>,
+    ttl:         Duration,
+    max_entries: usize,
+    cache:       Arc<Mutex<Cache>>,
+}
+
+impl CachingResolver {
+    /// Create a new resolver caching the addresses found by `inner` for `ttl`, for at most `max_entries` hosts
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn new(inner: Arc<dyn Resolver>, ttl: Duration, max_entries: usize) -> CachingResolver {
+        CachingResolver {
+            inner,
+            ttl,
+            max_entries,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Resolver for CachingResolver {
+    fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static> {
+        let now = Instant::now();
+        if let Some(&(expiration, ref ips)) = lock(&self.cache).get(host) {
+            if expiration > now {
+                return Box::new(futures::future::ok(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect()));
+            }
+        }
+        let cache       = self.cache.clone();
+        let host        = host.to_string();
+        let ttl         = self.ttl;
+        let max_entries = self.max_entries;
+        Box::new(self.inner.resolve(&host, port).map(move |addrs| {
+            if max_entries > 0 && !addrs.is_empty() {
+                let now       = Instant::now();
+                let mut cache = lock(&cache);
+                cache.retain(|_, &mut (expiration, _)| expiration > now);
+                if cache.len() >= max_entries && !cache.contains_key(&host) {
+                    let oldest = cache.iter().min_by_key(|&(_, &(expiration, _))| expiration).map(|(host, _)| host.clone());
+                    if let Some(oldest) = oldest {
+                        cache.remove(&oldest);
+                    }
+                }
+                cache.insert(host, (now + ttl, addrs.iter().map(SocketAddr::ip).collect()));
+            }
+            addrs
+        }))
+    }
+}
+
+fn lock(cache: &Mutex<Cache>) -> MutexGuard<'_, Cache> {
+    // The lock is never held while doing anything which could panic
+    cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Resolve every host to 192.0.2.x, x being the number of lookups done so far, `fail` and `empty` excepted
+    #[derive(Debug, Default)]
+    struct CountingResolver {
+        lookups: AtomicUsize,
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve(&self, host: &str, port: u16) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = io::Error> + Send + 'static> {
+            let lookup = self.lookups.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::new(match host {
+                "fail"  => futures::future::err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+                "empty" => futures::future::ok(Vec::new()),
+                _       => futures::future::ok(vec![SocketAddr::new(IpAddr::from([192, 0, 2, lookup as u8]), port)]),
+            })
+        }
+    }
+
+    fn resolver(ttl: Duration, max_entries: usize) -> (Arc<CountingResolver>, CachingResolver) {
+        let inner = Arc::new(CountingResolver::default());
+        (inner.clone(), CachingResolver::new(inner, ttl, max_entries))
+    }
+
+    fn resolve(resolver: &CachingResolver, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        resolver.resolve(host, port).wait()
+    }
+
+    fn lookups(inner: &CountingResolver) -> usize {
+        inner.lookups.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn cache_addresses_for_every_port() {
+        let (inner, resolver) = resolver(Duration::from_secs(60), 10);
+        assert_eq!(resolve(&resolver, "broker", 5672).unwrap(), vec!["192.0.2.1:5672".parse().unwrap()]);
+        assert_eq!(resolve(&resolver, "broker", 5671).unwrap(), vec!["192.0.2.1:5671".parse().unwrap()]);
+        assert_eq!(lookups(&inner), 1);
+    }
+
+    #[test]
+    fn expire_entries() {
+        let (inner, resolver) = resolver(Duration::from_millis(50), 10);
+        resolve(&resolver, "broker", 5672).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(resolve(&resolver, "broker", 5672).unwrap(), vec!["192.0.2.2:5672".parse().unwrap()]);
+        assert_eq!(lookups(&inner), 2);
+    }
+
+    #[test]
+    fn evict_the_entry_closest_to_expiration() {
+        let (inner, resolver) = resolver(Duration::from_secs(60), 2);
+        resolve(&resolver, "first", 5672).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        resolve(&resolver, "second", 5672).unwrap();
+        resolve(&resolver, "third", 5672).unwrap();
+        assert_eq!(lookups(&inner), 3);
+        resolve(&resolver, "second", 5672).unwrap();
+        resolve(&resolver, "third", 5672).unwrap();
+        assert_eq!(lookups(&inner), 3);
+        resolve(&resolver, "first", 5672).unwrap();
+        assert_eq!(lookups(&inner), 4);
+    }
+
+    #[test]
+    fn do_not_cache_failures() {
+        let (inner, resolver) = resolver(Duration::from_secs(60), 10);
+        assert!(resolve(&resolver, "fail", 5672).is_err());
+        assert!(resolve(&resolver, "fail", 5672).is_err());
+        assert!(resolve(&resolver, "empty", 5672).unwrap().is_empty());
+        assert!(resolve(&resolver, "empty", 5672).unwrap().is_empty());
+        assert_eq!(lookups(&inner), 4);
+    }
+
+    #[test]
+    fn disable_cache_without_entries() {
+        let (inner, resolver) = resolver(Duration::from_secs(60), 0);
+        resolve(&resolver, "broker", 5672).unwrap();
+        resolve(&resolver, "broker", 5672).unwrap();
+        assert_eq!(lookups(&inner), 2);
+    }
+}
@@ -0,0 +1,191 @@
+use futures::{self, future::{Either, Future, Loop}};
+use lapin_futures::error::ErrorKind as ProtocolErrorKind;
+use tokio_timer::Delay;
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind};
+
+/// The policy used to retry a connection that failed because of a transient error.
+///
+/// Only failures which are likely to go away are retried: a refused, reset or timed out connection, or a
+/// handshake which didn't complete in time. Other failures, like a rejected certificate, an invalid uri or
+/// the broker closing the connection during the negotiation, as it does when rejecting the credentials, fail right away.
+#[derive(Clone, Debug)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first one
+    pub max_attempts:  u32,
+    /// The time to wait before the first retry
+    pub initial_delay: Duration,
+    /// The factor by which the delay is multiplied after each retry
+    pub multiplier:    u32,
+    /// The longest time to wait between two attempts, however many attempts were made
+    pub max_delay:     Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts:  5,
+            initial_delay: Duration::from_millis(500),
+            multiplier:    2,
+            max_delay:     Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run the future produced by `attempt` until it succeeds or fails with an error which shouldn't be retried.
+    ///
+    /// The last error is returned once `max_attempts` attempts have failed.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn retry<T, Attempt, F>(&self, mut attempt: Attempt) -> Box<dyn Future<Item = T, Error = Error> + Send + 'static>
+        where T:       Send + 'static,
+              Attempt: FnMut() -> F + Send + 'static,
+              F:       Future<Item = T, Error = Error> + Send + 'static {
+        let max_attempts = self.max_attempts;
+        let multiplier   = self.multiplier;
+        let max_delay    = self.max_delay;
+
+        Box::new(futures::future::loop_fn((1, self.initial_delay.min(max_delay)), move |(attempts, delay)| {
+            attempt().then(move |res| match res {
+                Ok(res)                                                    => Either::A(futures::future::ok(Loop::Break(res))),
+                Err(err) if attempts >= max_attempts || !is_transient(&err) => Either::A(futures::future::err(err)),
+                Err(_)                                                     => Either::B(Delay::new(Instant::now() + delay).map_err(|e| {
+                    ErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::Other, format!("Timer error: {}", e))).into()
+                }).map(move |()| Loop::Continue((attempts + 1, next_delay(delay, multiplier, max_delay))))),
+            })
+        }))
+    }
+}
+
+/// Multiply `delay` by `multiplier`, without ever going over `max_delay`
+fn next_delay(delay: Duration, multiplier: u32, max_delay: Duration) -> Duration {
+    delay.checked_mul(multiplier).map_or(max_delay, |delay| delay.min(max_delay))
+}
+
+fn is_transient(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::ConnectionFailed(ref e)          |
+        ErrorKind::TlsHandshakeFailed(ref e)        => is_transient_io(e),
+        ErrorKind::HandshakeTimedOut                => true,
+        ErrorKind::ProtocolError(ref e)             => match *e.kind() {
+            ProtocolErrorKind::ConnectionFailed(ref e) => is_transient_io(e),
+            // ConnectionClosed is also how a broker rejecting the credentials or the vhost is seen, lapin not handling connection.close
+            _                                          => false,
+        },
+        ErrorKind::UriParsingError(_)               |
+        ErrorKind::InvalidDomainName(_)             |
+        ErrorKind::InvalidTlsConfiguration(_)       |
+        ErrorKind::PinnedCertificateMismatch        |
+        ErrorKind::Cancelled                        |
+        ErrorKind::__Nonexhaustive                  => false,
+    }
+}
+
+fn is_transient_io(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::TimedOut |
+        io::ErrorKind::Interrupted | io::ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "test")
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy { max_attempts, initial_delay: Duration::from_millis(1), multiplier: 2, max_delay: Duration::from_millis(5) }
+    }
+
+    /// Retry an attempt always failing with `err`, returning the number of attempts made
+    fn attempts<E: Fn() -> Error + Send + 'static>(policy: &RetryPolicy, err: E) -> usize {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter  = attempts.clone();
+        let res      = tokio::runtime::current_thread::block_on_all(policy.retry(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            futures::future::err::<(), _>(err())
+        }));
+        assert!(res.is_err());
+        attempts.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn increase_delay_exponentially() {
+        let max_delay = Duration::from_secs(30);
+        assert_eq!(next_delay(Duration::from_millis(500), 2, max_delay), Duration::from_secs(1));
+        assert_eq!(next_delay(Duration::from_secs(1), 3, max_delay), Duration::from_secs(3));
+        assert_eq!(next_delay(Duration::from_secs(1), 1, max_delay), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cap_delay() {
+        let max_delay = Duration::from_secs(30);
+        assert_eq!(next_delay(Duration::from_secs(20), 2, max_delay), max_delay);
+        assert_eq!(next_delay(max_delay, 2, max_delay), max_delay);
+        // Would overflow a Duration
+        assert_eq!(next_delay(Duration::from_secs(u64::max_value() / 2), u32::max_value(), max_delay), max_delay);
+        let mut delay = Duration::from_millis(500);
+        for _ in 0..100 {
+            delay = next_delay(delay, 10, max_delay);
+        }
+        assert_eq!(delay, max_delay);
+    }
+
+    #[test]
+    fn classify_transient_errors() {
+        assert!(is_transient(&ErrorKind::ConnectionFailed(io_error(io::ErrorKind::ConnectionRefused)).into()));
+        assert!(is_transient(&ErrorKind::ConnectionFailed(io_error(io::ErrorKind::TimedOut)).into()));
+        assert!(is_transient(&ErrorKind::TlsHandshakeFailed(io_error(io::ErrorKind::ConnectionReset)).into()));
+        assert!(is_transient(&ErrorKind::HandshakeTimedOut.into()));
+        assert!(is_transient(&ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionFailed(io_error(io::ErrorKind::BrokenPipe)).into()).into()));
+    }
+
+    #[test]
+    fn classify_permanent_errors() {
+        assert!(!is_transient(&ErrorKind::ConnectionFailed(io_error(io::ErrorKind::PermissionDenied)).into()));
+        assert!(!is_transient(&ErrorKind::TlsHandshakeFailed(io_error(io::ErrorKind::InvalidData)).into()));
+        assert!(!is_transient(&ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionClosed.into()).into()));
+        assert!(!is_transient(&ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionFailed(io_error(io::ErrorKind::InvalidInput)).into()).into()));
+        assert!(!is_transient(&ErrorKind::UriParsingError("invalid".to_string()).into()));
+        assert!(!is_transient(&ErrorKind::InvalidDomainName("broker".to_string()).into()));
+        assert!(!is_transient(&ErrorKind::PinnedCertificateMismatch.into()));
+        assert!(!is_transient(&ErrorKind::Cancelled.into()));
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        assert_eq!(attempts(&policy(3), || ErrorKind::ConnectionFailed(io_error(io::ErrorKind::ConnectionRefused)).into()), 3);
+        assert_eq!(attempts(&policy(1), || ErrorKind::HandshakeTimedOut.into()), 1);
+    }
+
+    #[test]
+    fn fail_fast_on_permanent_errors() {
+        assert_eq!(attempts(&policy(3), || ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionClosed.into()).into()), 1);
+    }
+
+    #[test]
+    fn stop_retrying_on_success() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter  = attempts.clone();
+        let res      = tokio::runtime::current_thread::block_on_all(policy(5).retry(move || {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => futures::future::err(ErrorKind::ConnectionFailed(io_error(io::ErrorKind::ConnectionReset)).into()),
+                n     => futures::future::ok(n),
+            }
+        }));
+        assert_eq!(res.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,179 @@
+use futures::{self, future::Future};
+use tokio_io::io::{read_exact, write_all};
+
+use std::io;
+use std::net::IpAddr;
+
+use crate::TcpStream;
+
+const VERSION: u8 = 0x05;
+
+/// Perform the SOCKS5 handshake on `stream`, asking the proxy to connect to `host:port`
+pub(crate) fn connect(stream: TcpStream, host: String, port: u16) -> Box<dyn Future<Item = TcpStream, Error = io::Error> + Send + 'static> {
+    let request = match connect_request(&host, port) {
+        Ok(request) => request,
+        Err(err)    => return Box::new(futures::future::err(err)),
+    };
+    Box::new(
+        write_all(stream, [VERSION, 0x01, 0x00]).and_then(|(stream, _)| {
+            read_exact(stream, [0u8; 2])
+        }).and_then(|(stream, reply)| {
+            match reply {
+                [VERSION, 0x00] => Ok(stream),
+                [VERSION, 0xff] => Err(error("SOCKS5 proxy requires an unsupported authentication method")),
+                _               => Err(error("Invalid SOCKS5 proxy reply")),
+            }
+        }).and_then(move |stream| {
+            write_all(stream, request)
+        }).and_then(|(stream, _)| {
+            read_exact(stream, [0u8; 4])
+        }).and_then(|(stream, reply)| {
+            match reply {
+                [VERSION, 0x00, _, atyp] => Ok((stream, atyp)),
+                [VERSION, code, _, _]    => Err(error(&format!("SOCKS5 proxy failed to connect: {}", reply_message(code)))),
+                _                        => Err(error("Invalid SOCKS5 proxy reply")),
+            }
+        }).and_then(|(stream, atyp)| {
+            let len: Box<dyn Future<Item = (TcpStream, usize), Error = io::Error> + Send + 'static> = match atyp {
+                0x01 => Box::new(futures::future::ok((stream, 4))),
+                0x04 => Box::new(futures::future::ok((stream, 16))),
+                0x03 => Box::new(read_exact(stream, [0u8; 1]).map(|(stream, len)| (stream, len[0] as usize))),
+                _    => Box::new(futures::future::err(error("Invalid SOCKS5 proxy bound address"))),
+            };
+            len
+        }).and_then(|(stream, len)| {
+            // Skip the bound address and port
+            read_exact(stream, vec![0u8; len + 2]).map(|(stream, _)| stream)
+        })
+    )
+}
+
+fn connect_request(host: &str, port: u16) -> io::Result<Vec<u8>> {
+    let mut request = vec![VERSION, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_)             => {
+            if host.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Host name too long for SOCKS5"));
+            }
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _    => "unknown error",
+    }
+}
+
+fn error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::{self, Ipv4Addr, TcpListener};
+    use std::thread;
+
+    /// Run `proxy` on the accepted connection while performing the handshake asking for `host:port`
+    fn handshake<F: FnOnce(net::TcpStream) + Send + 'static>(host: &str, port: u16, proxy: F) -> io::Result<Vec<u8>> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr     = listener.local_addr().unwrap();
+        let proxy    = thread::spawn(move || proxy(listener.accept().unwrap().0));
+        let host     = host.to_string();
+        let res      = tokio::runtime::current_thread::block_on_all(TcpStream::connect(&addr).and_then(move |stream| connect(stream, host, port)).and_then(|stream| {
+            // Whatever follows the handshake is left to the caller
+            tokio_io::io::read_to_end(stream, Vec::new()).map(|(_, data)| data)
+        }));
+        proxy.join().unwrap();
+        res
+    }
+
+    fn expect(stream: &mut net::TcpStream, expected: &[u8]) {
+        let mut actual = vec![0u8; expected.len()];
+        stream.read_exact(&mut actual).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn encode_ipv4_request() {
+        assert_eq!(connect_request("192.0.2.1", 5671).unwrap(), vec![5, 1, 0, 1, 192, 0, 2, 1, 0x16, 0x27]);
+    }
+
+    #[test]
+    fn encode_ipv6_request() {
+        assert_eq!(connect_request("2001:db8::1", 5672).unwrap(), vec![5, 1, 0, 4, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x16, 0x28]);
+    }
+
+    #[test]
+    fn encode_domain_request() {
+        assert_eq!(connect_request("broker", 5672).unwrap(), [&[5, 1, 0, 3, 6][..], b"broker", &[0x16, 0x28]].concat());
+        assert!(connect_request(&"a".repeat(255), 5672).is_ok());
+        assert!(connect_request(&"a".repeat(256), 5672).is_err());
+    }
+
+    #[test]
+    fn complete_handshake() {
+        let data = handshake("broker", 5672, |mut stream| {
+            expect(&mut stream, &[5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+            expect(&mut stream, &connect_request("broker", 5672).unwrap());
+            // Bound to a domain name, which has to be skipped along with the port
+            stream.write_all(&[5, 0, 0, 3, 5]).unwrap();
+            stream.write_all(b"proxy\x00\x50AMQP").unwrap();
+        }).unwrap();
+        assert_eq!(data, b"AMQP");
+    }
+
+    #[test]
+    fn reject_authentication() {
+        let err = handshake("broker", 5672, |mut stream| {
+            expect(&mut stream, &[5, 1, 0]);
+            stream.write_all(&[5, 0xff]).unwrap();
+        }).unwrap_err();
+        assert!(err.to_string().contains("authentication"), "{}", err);
+    }
+
+    #[test]
+    fn report_connect_failure() {
+        let err = handshake("192.0.2.1", 5672, |mut stream| {
+            expect(&mut stream, &[5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+            expect(&mut stream, &connect_request("192.0.2.1", 5672).unwrap());
+            stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        }).unwrap_err();
+        assert!(err.to_string().contains("connection refused"), "{}", err);
+    }
+
+    #[test]
+    fn reject_invalid_reply() {
+        assert!(handshake("broker", 5672, |mut stream| {
+            expect(&mut stream, &[5, 1, 0]);
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").unwrap();
+        }).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use bytes::{Buf, BufMut};
+use log::debug;
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+const FRAME_METHOD:    u8 = 1;
+const FRAME_HEARTBEAT: u8 = 8;
+const FRAME_END:       u8 = 0xCE;
+
+/// How the `MockBroker` handles a connection, to inject failures in the connection process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[deprecated(note = "use lapin directly instead")]
+pub enum MockFailure {
+    /// Complete the handshake, then answer the `channel.open` and `channel.close` methods
+    None,
+    /// Close the TCP connection as soon as it's accepted
+    CloseOnAccept,
+    /// Close the connection after receiving the protocol header, without sending `connection.start`
+    CloseAfterHeader,
+    /// Close the connection after sending `connection.start`, like a broker rejecting the credentials
+    CloseAfterStart,
+}
+
+/// A fake AMQP broker listening on the loopback interface, to exercise the connection process without a real broker.
+///
+/// Each accepted connection is handled in its own thread according to the next `MockFailure` of its script,
+/// the connections accepted once the script is exhausted being handled normally. Only plain `amqp` connections
+/// are supported, and only the negotiation and the opening and closing of channels is implemented.
+#[deprecated(note = "use lapin directly instead")]
+pub struct MockBroker {
+    addr:        SocketAddr,
+    connections: Arc<AtomicUsize>,
+    stopped:     Arc<AtomicBool>,
+}
+
+impl MockBroker {
+    /// Start a broker on a random port, handling the successive connections according to `script`
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn start(script: Vec<MockFailure>) -> io::Result<MockBroker> {
+        let listener    = TcpListener::bind("127.0.0.1:0")?;
+        let addr        = listener.local_addr()?;
+        let connections = Arc::new(AtomicUsize::new(0));
+        let stopped     = Arc::new(AtomicBool::new(false));
+        let script      = Arc::new(Mutex::new(script.into_iter()));
+        let accepted    = connections.clone();
+        let stop        = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let id      = accepted.fetch_add(1, Ordering::SeqCst);
+                    let failure = script.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next().unwrap_or(MockFailure::None);
+                    debug!("mock broker: connection {} accepted, failure={:?}", id, failure);
+                    thread::spawn(move || {
+                        if let Err(err) = serve(stream, failure) {
+                            debug!("mock broker: connection {} failed: {}", id, err);
+                        }
+                    });
+                }
+            }
+        });
+        Ok(MockBroker { addr, connections, stopped })
+    }
+
+    /// The address the broker listens on
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// An `amqp` uri to connect to the broker, any credentials and vhost being accepted
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn uri(&self) -> String {
+        format!("amqp://guest:guest@{}/%2f", self.addr)
+    }
+
+    /// The number of connections accepted so far
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the accepting thread up so that it notices it has to stop
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(mut stream: TcpStream, failure: MockFailure) -> io::Result<()> {
+    if failure == MockFailure::CloseOnAccept {
+        return stream.shutdown(Shutdown::Both);
+    }
+    let mut protocol_header = [0u8; 8];
+    stream.read_exact(&mut protocol_header)?;
+    if &protocol_header != b"AMQP\x00\x00\x09\x01" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid protocol header"));
+    }
+    if failure == MockFailure::CloseAfterHeader {
+        return stream.shutdown(Shutdown::Both);
+    }
+
+    // connection.start: version 0-9, no server properties, PLAIN mechanism, en_US locale
+    let mut start = vec![0, 9];
+    start.put_u32_be(0);
+    long_string(&mut start, b"PLAIN");
+    long_string(&mut start, b"en_US");
+    send_method(&mut stream, 0, (10, 10), &start)?;
+    if failure == MockFailure::CloseAfterStart {
+        return stream.shutdown(Shutdown::Both);
+    }
+    expect_method(&mut stream, (10, 11))?; // connection.start-ok
+
+    // connection.tune: 2047 channels, 128KiB frames, no heartbeat
+    let mut tune = Vec::with_capacity(8);
+    tune.put_u16_be(2047);
+    tune.put_u32_be(131_072);
+    tune.put_u16_be(0);
+    send_method(&mut stream, 0, (10, 30), &tune)?;
+    expect_method(&mut stream, (10, 31))?; // connection.tune-ok
+    expect_method(&mut stream, (10, 40))?; // connection.open
+    send_method(&mut stream, 0, (10, 41), &[0])?;
+
+    loop {
+        match read_frame(&mut stream)? {
+            (FRAME_METHOD, channel, (20, 10)) => send_method(&mut stream, channel, (20, 11), &[0, 0, 0, 0])?, // channel.open
+            (FRAME_METHOD, channel, (20, 40)) => send_method(&mut stream, channel, (20, 41), &[])?,           // channel.close
+            (FRAME_HEARTBEAT, _, _)           => {},
+            (kind, channel, method)           => debug!("mock broker: ignoring frame {} {:?} on channel {}", kind, method, channel),
+        }
+    }
+}
+
+fn long_string(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.put_u32_be(value.len() as u32);
+    buf.extend_from_slice(value);
+}
+
+fn send_method(stream: &mut TcpStream, channel: u16, (class, method): (u16, u16), arguments: &[u8]) -> io::Result<()> {
+    let mut frame = vec![FRAME_METHOD];
+    frame.put_u16_be(channel);
+    frame.put_u32_be(arguments.len() as u32 + 4);
+    frame.put_u16_be(class);
+    frame.put_u16_be(method);
+    frame.extend_from_slice(arguments);
+    frame.push(FRAME_END);
+    stream.write_all(&frame)
+}
+
+fn expect_method(stream: &mut TcpStream, expected: (u16, u16)) -> io::Result<()> {
+    match read_frame(stream)? {
+        (FRAME_METHOD, _, method) if method == expected => Ok(()),
+        (kind, _, method)                               => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected method {:?}, got frame {} {:?}", expected, kind, method))),
+    }
+}
+
+/// Read the next frame, returning its type, its channel and, for method frames, its class and method ids
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, u16, (u16, u16))> {
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header)?;
+    let mut fields  = io::Cursor::new(&header[1..]);
+    let channel     = fields.get_u16_be();
+    let mut payload = vec![0u8; fields.get_u32_be() as usize + 1];
+    stream.read_exact(&mut payload)?;
+    if payload[payload.len() - 1] != FRAME_END {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame end"));
+    }
+    let method = if header[0] == FRAME_METHOD && payload.len() >= 5 {
+        let mut ids = io::Cursor::new(&payload[..4]);
+        (ids.get_u16_be(), ids.get_u16_be())
+    } else {
+        (0, 0)
+    };
+    Ok((header[0], channel, method))
+}
@@ -1,6 +1,6 @@
 #![deny(missing_docs)]
 #![warn(rust_2018_idioms)]
-#![doc(html_root_url = "https://docs.rs/lapin-futures-openssl/0.10.1/")]
+#![doc(html_root_url = "https://docs.rs/lapin-futures-openssl/0.11.0/")]
 
 //! lapin-futures-openssl
 //!
@@ -0,0 +1,126 @@
+// The in-memory pipe used here is a unix socket pair
+#[cfg(unix)]
+mod unix {
+    use failure::Error;
+    use futures::future::Future;
+    use lapin_futures_rustls::lapin;
+    use lapin::client::{Client, ConnectionOptions};
+
+    use std::io::{self, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    const FRAME_METHOD: u8 = 1;
+    const FRAME_END:    u8 = 0xCE;
+
+    pub fn main() {
+        // An in-memory duplex pipe, one end for the client, the other one for the fake broker
+        let (client_end, broker_end) = UnixStream::pair().expect("failed to create the pipe");
+        let broker                   = thread::spawn(move || mock_broker(broker_end));
+        let stream                   = tokio::net::UnixStream::from_std(client_end, &tokio::reactor::Handle::default()).expect("failed to register the pipe");
+
+        tokio::run(
+            Client::connect(stream, ConnectionOptions { heartbeat: 0, ..ConnectionOptions::default() }).map_err(Error::from).and_then(|(client, mut heartbeat)| {
+                println!("Connected with frame_max={}!", client.configuration.frame_max);
+                if let Some(heartbeat_handle) = heartbeat.handle() {
+                    heartbeat_handle.stop();
+                }
+                client.create_channel().and_then(|channel| {
+                    println!("Channel {} opened, closing it.", channel.id);
+                    channel.close(200, "Bye")
+                }).map_err(Error::from)
+            }).map_err(|err| {
+                eprintln!("amqp error: {:?}", err);
+            })
+        );
+
+        broker.join().expect("the broker panicked").expect("the broker failed");
+    }
+
+    /// Play the broker side of the connection negotiation, then of a channel opening and closing
+    fn mock_broker(mut stream: UnixStream) -> io::Result<()> {
+        let mut protocol_header = [0u8; 8];
+        stream.read_exact(&mut protocol_header)?;
+        assert_eq!(&protocol_header, b"AMQP\x00\x00\x09\x01");
+
+        // connection.start: version 0-9, no server properties, PLAIN mechanism, en_US locale
+        let mut start = vec![0, 9];
+        start.extend_from_slice(&u32_bytes(0));
+        long_string(&mut start, b"PLAIN");
+        long_string(&mut start, b"en_US");
+        send_method(&mut stream, 0, (10, 10), &start)?;
+        expect_method(&mut stream, (10, 11))?; // connection.start-ok
+
+        // connection.tune: no channel limit, 128KiB frames, no heartbeat
+        let mut tune = u16_bytes(0).to_vec();
+        tune.extend_from_slice(&u32_bytes(131_072));
+        tune.extend_from_slice(&u16_bytes(0));
+        send_method(&mut stream, 0, (10, 30), &tune)?;
+        expect_method(&mut stream, (10, 31))?; // connection.tune-ok
+        expect_method(&mut stream, (10, 40))?; // connection.open
+        send_method(&mut stream, 0, (10, 41), &[0])?;
+
+        let channel = expect_method(&mut stream, (20, 10))?; // channel.open
+        send_method(&mut stream, channel, (20, 11), &u32_bytes(0))?;
+        expect_method(&mut stream, (20, 40))?; // channel.close
+        send_method(&mut stream, channel, (20, 41), &[])
+    }
+
+    fn long_string(buf: &mut Vec<u8>, value: &[u8]) {
+        buf.extend_from_slice(&u32_bytes(value.len() as u32));
+        buf.extend_from_slice(value);
+    }
+
+    fn send_method(stream: &mut UnixStream, channel: u16, (class, method): (u16, u16), arguments: &[u8]) -> io::Result<()> {
+        let mut frame = vec![FRAME_METHOD];
+        frame.extend_from_slice(&u16_bytes(channel));
+        frame.extend_from_slice(&u32_bytes(arguments.len() as u32 + 4));
+        frame.extend_from_slice(&u16_bytes(class));
+        frame.extend_from_slice(&u16_bytes(method));
+        frame.extend_from_slice(arguments);
+        frame.push(FRAME_END);
+        stream.write_all(&frame)
+    }
+
+    /// Read the next frame, check that it's the expected method and return the channel it was sent on
+    fn expect_method(stream: &mut UnixStream, (class, method): (u16, u16)) -> io::Result<u16> {
+        let mut header = [0u8; 7];
+        stream.read_exact(&mut header)?;
+        let channel     = read_u16(&header[1..]);
+        let mut payload = vec![0u8; read_u32(&header[3..]) as usize + 1];
+        stream.read_exact(&mut payload)?;
+        if header[0] != FRAME_METHOD || payload.len() < 5 || payload[payload.len() - 1] != FRAME_END {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame"));
+        }
+        let actual = (read_u16(&payload[0..]), read_u16(&payload[2..]));
+        if actual != (class, method) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected method {:?}, got {:?}", (class, method), actual)));
+        }
+        println!("broker received method {:?} on channel {}", actual, channel);
+        Ok(channel)
+    }
+
+    fn u16_bytes(value: u16) -> [u8; 2] {
+        [(value >> 8) as u8, value as u8]
+    }
+
+    fn u32_bytes(value: u32) -> [u8; 4] {
+        [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+    }
+
+    fn read_u16(bytes: &[u8]) -> u16 {
+        u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+    }
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        u32::from(read_u16(bytes)) << 16 | u32::from(read_u16(&bytes[2..]))
+    }
+}
+
+#[cfg(unix)]
+fn main() {
+    unix::main();
+}
+
+#[cfg(not(unix))]
+fn main() {}
@@ -0,0 +1,198 @@
+use tokio_rustls::rustls::{internal::pemfile, Certificate, PrivateKey};
+use untrusted::{Input, Reader};
+
+use std::fs;
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SEQUENCE:         u8 = 0x30;
+const INTEGER:          u8 = 0x02;
+const EXPLICIT_VERSION: u8 = 0xA0;
+const UTC_TIME:         u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// Extract the end of the validity period (`notAfter`) of an X.509 certificate, `None` if it can't be parsed
+pub(crate) fn not_after(cert: &Certificate) -> Option<SystemTime> {
+    Input::from(&cert.0).read_all((), |certificate| {
+        let tbs = expect_tlv(certificate, SEQUENCE)?.read_all((), |certificate| {
+            let tbs = expect_tlv(certificate, SEQUENCE)?;
+            // signatureAlgorithm and signatureValue
+            certificate.skip_to_end();
+            Ok(tbs)
+        })?;
+        tbs.read_all((), |tbs| {
+            if tbs.peek(EXPLICIT_VERSION) {
+                expect_tlv(tbs, EXPLICIT_VERSION)?;
+            }
+            expect_tlv(tbs, INTEGER)?;  // serialNumber
+            expect_tlv(tbs, SEQUENCE)?; // signature
+            expect_tlv(tbs, SEQUENCE)?; // issuer
+            let validity = expect_tlv(tbs, SEQUENCE)?;
+            // subject, subjectPublicKeyInfo and extensions
+            tbs.skip_to_end();
+            validity.read_all((), |validity| {
+                read_tlv(validity)?; // notBefore
+                let (tag, time) = read_tlv(validity)?;
+                parse_time(tag, time.as_slice_less_safe())
+            })
+        })
+    }).ok()
+}
+
+/// Load a PEM certificate chain and a PEM private key, either PKCS#8 or RSA, the first one found being used
+pub(crate) fn load_client_cert(cert_path: &Path, key_path: &Path) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+    let certs = pemfile::certs(&mut io::BufReader::new(open(cert_path)?)).map_err(|()| invalid_pem(cert_path, "certificate"))?;
+    if certs.is_empty() {
+        return Err(invalid_pem(cert_path, "certificate"));
+    }
+    let mut keys = io::BufReader::new(open(key_path)?);
+    let mut key  = pemfile::pkcs8_private_keys(&mut keys).map_err(|()| invalid_pem(key_path, "private key"))?;
+    if key.is_empty() {
+        keys.seek(SeekFrom::Start(0))?;
+        key = pemfile::rsa_private_keys(&mut keys).map_err(|()| invalid_pem(key_path, "private key"))?;
+    }
+    let key = key.into_iter().next().ok_or_else(|| invalid_pem(key_path, "PKCS#8 or RSA private key"))?;
+    Ok((certs, key))
+}
+
+fn open(path: &Path) -> io::Result<fs::File> {
+    fs::File::open(path).map_err(|e| io::Error::new(e.kind(), format!("Failed to open '{}': {}", path.display(), e)))
+}
+
+fn invalid_pem(path: &Path, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("No valid PEM {} found in '{}'", what, path.display()))
+}
+
+fn read_tlv<'a>(reader: &mut Reader<'a>) -> Result<(u8, Input<'a>), ()> {
+    let tag    = reader.read_byte().map_err(|_| ())?;
+    let length = match reader.read_byte().map_err(|_| ())? {
+        length if length < 0x80       => usize::from(length),
+        length if length - 0x80 <= 4  => {
+            let mut value = 0usize;
+            for _ in 0..(length - 0x80) {
+                value = (value << 8) | usize::from(reader.read_byte().map_err(|_| ())?);
+            }
+            value
+        },
+        _                             => return Err(()),
+    };
+    reader.skip_and_get_input(length).map(|value| (tag, value)).map_err(|_| ())
+}
+
+fn expect_tlv<'a>(reader: &mut Reader<'a>, tag: u8) -> Result<Input<'a>, ()> {
+    match read_tlv(reader)? {
+        (actual, value) if actual == tag => Ok(value),
+        _                                => Err(()),
+    }
+}
+
+/// Parse an UTCTime (`YYMMDDHHMMSSZ`) or a GeneralizedTime (`YYYYMMDDHHMMSSZ`)
+fn parse_time(tag: u8, time: &[u8]) -> Result<SystemTime, ()> {
+    // Only ASCII is valid, and slicing the string below would panic on a multibyte character
+    if !time.is_ascii() {
+        return Err(());
+    }
+    let time = str::from_utf8(time).map_err(|_| ())?;
+    let (year, rest) = match (tag, time.len()) {
+        (UTC_TIME, 13)         => {
+            let year = number(&time[0..2])?;
+            (if year >= 50 { 1900 + year } else { 2000 + year }, &time[2..])
+        },
+        (GENERALIZED_TIME, 15) => (number(&time[0..4])?, &time[4..]),
+        _                      => return Err(()),
+    };
+    if !rest.ends_with('Z') {
+        return Err(());
+    }
+    let (month, day, hour, minute, second) = (number(&rest[0..2])?, number(&rest[2..4])?, number(&rest[4..6])?, number(&rest[6..8])?, number(&rest[8..10])?);
+    if year < 1970 || month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return Err(());
+    }
+    let seconds = days_since_epoch(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn number(digits: &str) -> Result<u64, ()> {
+    if digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().map_err(|_| ())
+    } else {
+        Err(())
+    }
+}
+
+/// Howard Hinnant's `days_from_civil`, restricted to dates after the epoch
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let year  = if month <= 2 { year - 1 } else { year };
+    let era   = year / 400;
+    let yoe   = year - era * 400;
+    let doy   = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe   = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut tlv = vec![tag];
+        if value.len() < 0x80 {
+            tlv.push(value.len() as u8);
+        } else {
+            tlv.extend_from_slice(&[0x82, (value.len() >> 8) as u8, value.len() as u8]);
+        }
+        tlv.extend_from_slice(value);
+        tlv
+    }
+
+    fn certificate(not_after: &[u8]) -> Certificate {
+        let validity = [tlv(UTC_TIME, b"190101000000Z"), tlv(UTC_TIME, not_after)].concat();
+        let tbs      = [tlv(EXPLICIT_VERSION, &tlv(INTEGER, &[2])), tlv(INTEGER, &[1]), tlv(SEQUENCE, &[]), tlv(SEQUENCE, &[0; 200]), tlv(SEQUENCE, &validity), tlv(SEQUENCE, &[])].concat();
+        Certificate(tlv(SEQUENCE, &[tlv(SEQUENCE, &tbs), tlv(SEQUENCE, &[]), tlv(0x03, &[0])].concat()))
+    }
+
+    #[test]
+    fn parse_utc_time() {
+        assert_eq!(parse_time(UTC_TIME, b"250101000000Z"), Ok(UNIX_EPOCH + Duration::from_secs(1_735_689_600)));
+        assert_eq!(parse_time(UTC_TIME, b"240229123456Z"), Ok(UNIX_EPOCH + Duration::from_secs(1_709_210_096)));
+    }
+
+    #[test]
+    fn parse_generalized_time() {
+        assert_eq!(parse_time(GENERALIZED_TIME, b"20500101000000Z"), Ok(UNIX_EPOCH + Duration::from_secs(2_524_608_000)));
+    }
+
+    #[test]
+    fn reject_invalid_times() {
+        assert_eq!(parse_time(UTC_TIME, b"2501010000Z"), Err(()));
+        assert_eq!(parse_time(UTC_TIME, b"250101000000"), Err(()));
+        assert_eq!(parse_time(UTC_TIME, b"251301000000Z"), Err(()));
+        assert_eq!(parse_time(UTC_TIME, b"491231235959Z"), Ok(UNIX_EPOCH + Duration::from_secs(2_524_607_999)));
+        assert_eq!(parse_time(UTC_TIME, b"500101000000Z"), Err(()));
+        assert_eq!(parse_time(GENERALIZED_TIME, b"250101000000Z"), Err(()));
+        assert_eq!(parse_time(UTC_TIME, b"-50101000000Z"), Err(()));
+    }
+
+    #[test]
+    fn reject_non_ascii_times() {
+        assert_eq!(parse_time(UTC_TIME, "2\u{e9}0101000000Z".as_bytes()), Err(()));
+        assert_eq!(parse_time(GENERALIZED_TIME, "20\u{e9}0101000000Z".as_bytes()), Err(()));
+    }
+
+    #[test]
+    fn not_after_of_certificate() {
+        assert_eq!(not_after(&certificate(b"250101000000Z")), Some(UNIX_EPOCH + Duration::from_secs(1_735_689_600)));
+        assert_eq!(not_after(&certificate("2\u{e9}0101000000Z".as_bytes())), None);
+    }
+
+    #[test]
+    fn not_after_of_truncated_certificate() {
+        let Certificate(der) = certificate(b"250101000000Z");
+        for len in 0..der.len() {
+            assert_eq!(not_after(&Certificate(der[..len].to_vec())), None);
+        }
+        assert_eq!(not_after(&Certificate(vec![SEQUENCE, 0x85, 0, 0, 0, 0, 0])), None);
+    }
+}
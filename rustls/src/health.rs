@@ -0,0 +1,28 @@
+use futures::future::Future;
+use lapin_futures_tls_internal::error::{Error, ErrorKind};
+use log::warn;
+use tokio_timer::Timeout;
+
+use std::time::Duration;
+
+use crate::{lapin, AMQPStream};
+use lapin::client::Client;
+
+/// Check that the connection is alive by opening and closing a channel on it, within `timeout`
+///
+/// The `Future` resolves to `false` instead of failing when the connection doesn't respond in time.
+pub(crate) fn ping<F: Future<Item = Client<AMQPStream>, Error = Error> + Send + 'static>(client: F, timeout: Duration) -> impl Future<Item = bool, Error = Error> + Send + 'static {
+    let probe = client.and_then(|client| {
+        client.create_channel().and_then(|channel| channel.close(200, "health check")).map_err(|e| ErrorKind::ProtocolError(e).into())
+    });
+    Timeout::new(probe, timeout).then(|res| match res {
+        Ok(())   => Ok(true),
+        Err(err) => {
+            match err.into_inner() {
+                Some(err) => warn!("health check failed: {}", err),
+                None      => warn!("health check timed out"),
+            }
+            Ok(false)
+        },
+    })
+}
@@ -0,0 +1,148 @@
+use futures::future::{Future, Shared, SharedError};
+use lapin_futures_tls_internal::error::{Error, ErrorKind};
+
+use std::io;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+
+use crate::{health, lapin, AMQPStream};
+use lapin::channel::{Channel, ConfirmSelectOptions};
+use lapin::client::{Client, HeartbeatHandle};
+use lapin::error::ErrorKind as ProtocolErrorKind;
+
+type Connection = (Client<AMQPStream>, Arc<Mutex<Option<HeartbeatHandle>>>);
+
+/// A `lapin_futures::client::Client` which only connects to the broker when it's first used.
+#[derive(Clone)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct LazyClient {
+    connection: Shared<Box<dyn Future<Item = Connection, Error = Error> + Send + 'static>>,
+}
+
+impl LazyClient {
+    pub(crate) fn new<F: Future<Item = (Client<AMQPStream>, HeartbeatHandle), Error = Error> + Send + 'static>(connect: F) -> LazyClient {
+        let connection: Box<dyn Future<Item = Connection, Error = Error> + Send + 'static> = Box::new(connect.map(|(client, heartbeat_handle)| (client, Arc::new(Mutex::new(Some(heartbeat_handle))))));
+        LazyClient {
+            connection: connection.shared(),
+        }
+    }
+
+    /// Method providing the `lapin_futures::client::Client` wrapped in a `Future`, connecting to the broker if it's not done yet
+    ///
+    /// All the clones of a `LazyClient` share the same connection, which is established only once.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn client(&self) -> impl Future<Item = Client<AMQPStream>, Error = Error> + Send + 'static {
+        self.connection.clone().map(|connection| connection.0.clone()).map_err(shared_error)
+    }
+
+    /// Create a `lapin_futures::channel::Channel`, connecting to the broker if it's not done yet
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn create_channel(&self) -> impl Future<Item = Channel<AMQPStream>, Error = Error> + Send + 'static {
+        self.client().and_then(|client| client.create_channel().map_err(|e| ErrorKind::ProtocolError(e).into()))
+    }
+
+    /// Create a `lapin_futures::channel::Channel` with publisher confirms, connecting to the broker if it's not done yet
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn create_confirm_channel(&self, options: ConfirmSelectOptions) -> impl Future<Item = Channel<AMQPStream>, Error = Error> + Send + 'static {
+        self.client().and_then(move |client| client.create_confirm_channel(options).map_err(|e| ErrorKind::ProtocolError(e).into()))
+    }
+
+    /// Check that the connection is alive by opening and closing a channel on it, connecting to the broker if it's not done yet
+    ///
+    /// The `Future` resolves to `false` if it fails or if the broker doesn't respond within `timeout`.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn ping(&self, timeout: Duration) -> impl Future<Item = bool, Error = Error> + Send + 'static {
+        health::ping(self.client(), timeout)
+    }
+
+    /// Stop the heartbeat of the connection, once it's established
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn stop_heartbeat(&self) -> impl Future<Item = (), Error = Error> + Send + 'static {
+        self.connection.clone().map(|connection| {
+            if let Some(heartbeat_handle) = connection.1.lock().ok().and_then(|mut handle| handle.take()) {
+                heartbeat_handle.stop();
+            }
+        }).map_err(shared_error)
+    }
+
+    /// Stop the heartbeat of the connection right away if it's already established, e.g. before dropping it
+    pub(crate) fn stop_established_heartbeat(&self) {
+        if let Some(Ok(connection)) = self.connection.peek() {
+            if let Some(heartbeat_handle) = connection.1.lock().ok().and_then(|mut handle| handle.take()) {
+                heartbeat_handle.stop();
+            }
+        }
+    }
+}
+
+/// The error is shared by all the clones of the `LazyClient`, give back a copy of its kind
+pub(crate) fn shared_error(err: SharedError<Error>) -> Error {
+    copy_kind(err.kind()).into()
+}
+
+/// Copy `kind`, down to the kind of the underlying `io::Error`s, keeping only the message of the errors which can't be copied
+fn copy_kind(kind: &ErrorKind) -> ErrorKind {
+    match *kind {
+        ErrorKind::UriParsingError(ref uri)       => ErrorKind::UriParsingError(uri.clone()),
+        ErrorKind::InvalidDomainName(ref host)    => ErrorKind::InvalidDomainName(host.clone()),
+        ErrorKind::ConnectionFailed(ref e)        => ErrorKind::ConnectionFailed(copy_io_error(e)),
+        ErrorKind::InvalidTlsConfiguration(ref e) => ErrorKind::InvalidTlsConfiguration(copy_io_error(e)),
+        ErrorKind::TlsHandshakeFailed(ref e)      => ErrorKind::TlsHandshakeFailed(copy_io_error(e)),
+        ErrorKind::HandshakeTimedOut              => ErrorKind::HandshakeTimedOut,
+        ErrorKind::PinnedCertificateMismatch      => ErrorKind::PinnedCertificateMismatch,
+        ErrorKind::Cancelled                      => ErrorKind::Cancelled,
+        ErrorKind::ProtocolError(ref e)           => ErrorKind::ProtocolError(copy_protocol_kind(e.kind()).into()),
+        _                                         => ErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::Other, kind.to_string())),
+    }
+}
+
+fn copy_protocol_kind(kind: &ProtocolErrorKind) -> ProtocolErrorKind {
+    match *kind {
+        ProtocolErrorKind::ChannelLimitReached     => ProtocolErrorKind::ChannelLimitReached,
+        ProtocolErrorKind::ChannelOpenFailed       => ProtocolErrorKind::ChannelOpenFailed,
+        ProtocolErrorKind::ConnectionClosed        => ProtocolErrorKind::ConnectionClosed,
+        ProtocolErrorKind::ConnectionFailed(ref e) => ProtocolErrorKind::ConnectionFailed(copy_io_error(e)),
+        ProtocolErrorKind::EmptyBasicGet           => ProtocolErrorKind::EmptyBasicGet,
+        ProtocolErrorKind::InvalidUri(ref uri)     => ProtocolErrorKind::InvalidUri(uri.clone()),
+        // The codec, timer and lapin-async errors can't be copied
+        _                                          => ProtocolErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::Other, kind.to_string())),
+    }
+}
+
+fn copy_io_error(err: &io::Error) -> io::Error {
+    io::Error::new(err.kind(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+
+    fn shared(kind: ErrorKind) -> Error {
+        LazyClient::new(future::err(kind.into())).client().wait().err().unwrap()
+    }
+
+    #[test]
+    fn shared_errors_keep_their_kind() {
+        match *shared(ErrorKind::HandshakeTimedOut).kind() {
+            ErrorKind::HandshakeTimedOut => {},
+            ref kind                     => panic!("unexpected error: {}", kind),
+        }
+        match *shared(ErrorKind::TlsHandshakeFailed(io::Error::new(io::ErrorKind::InvalidData, "bad certificate"))).kind() {
+            ErrorKind::TlsHandshakeFailed(ref e) => assert_eq!((e.kind(), e.to_string()), (io::ErrorKind::InvalidData, "bad certificate".to_string())),
+            ref kind                             => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn shared_protocol_errors_keep_their_kind() {
+        match *shared(ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionClosed.into())).kind() {
+            ErrorKind::ProtocolError(ref e) => match *e.kind() {
+                ProtocolErrorKind::ConnectionClosed => {},
+                ref kind                            => panic!("unexpected protocol error: {}", kind),
+            },
+            ref kind                        => panic!("unexpected error: {}", kind),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use futures::future::Future;
+use lapin_futures_tls_internal::{error::{Error, ErrorKind}, TcpStream};
+use ring::digest;
+use tokio_rustls::{rustls::{ClientConfig, ClientSession, Session}, TlsStream};
+
+use std::{error, fmt, io};
+use std::sync::Arc;
+
+/// Wrap the default connector to check the SHA-256 fingerprint of the server certificate once the handshake is done
+pub(crate) fn connector(config: Arc<ClientConfig>, server_name: Option<String>, fingerprint: [u8; 32]) -> impl FnOnce(String, TcpStream) -> Box<dyn Future<Item = Box<TlsStream<TcpStream, ClientSession>>, Error = io::Error> + Send + 'static> + Send + 'static {
+    let connector = crate::connector(config, server_name);
+    move |host, stream| {
+        Box::new(connector(host, stream).and_then(move |stream| {
+            check_fingerprint(&stream, &fingerprint).map(|()| stream)
+        }))
+    }
+}
+
+/// Turn the handshake failure caused by a fingerprint mismatch into `ErrorKind::PinnedCertificateMismatch`
+pub(crate) fn map_error(err: Error) -> Error {
+    let mismatch = match *err.kind() {
+        ErrorKind::TlsHandshakeFailed(ref e) => e.get_ref().map(|e| e.is::<FingerprintMismatch>()).unwrap_or(false),
+        _                                    => false,
+    };
+    if mismatch {
+        ErrorKind::PinnedCertificateMismatch.into()
+    } else {
+        err
+    }
+}
+
+fn check_fingerprint(stream: &TlsStream<TcpStream, ClientSession>, fingerprint: &[u8; 32]) -> io::Result<()> {
+    let leaf = stream.get_ref().1.get_peer_certificates().and_then(|certs| certs.into_iter().next());
+    match leaf {
+        Some(ref cert) if digest::digest(&digest::SHA256, &cert.0).as_ref() == &fingerprint[..] => Ok(()),
+        _                                                                                       => Err(io::Error::new(io::ErrorKind::InvalidData, FingerprintMismatch)),
+    }
+}
+
+#[derive(Debug)]
+struct FingerprintMismatch;
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Server certificate doesn't match the pinned fingerprint")
+    }
+}
+
+impl error::Error for FingerprintMismatch {}
@@ -0,0 +1,129 @@
+use futures::future::{self, Either, Future};
+use lapin_futures_tls_internal::error::{Error, ErrorKind};
+use log::warn;
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{lapin, AMQPConnectionBuilder, AMQPStream, LazyClient};
+use lapin::channel::Channel;
+use lapin::error::{Error as ProtocolError, ErrorKind as ProtocolErrorKind};
+
+/// A pool of up to `max_connections` connections to the same broker, handing out channels.
+///
+/// Connections are established when first needed and replaced when they stop working.
+#[derive(Clone)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct AMQPPool {
+    builder:         AMQPConnectionBuilder,
+    max_connections: usize,
+    connections:     Arc<Mutex<Connections>>,
+}
+
+#[derive(Default)]
+struct Connections {
+    clients: Vec<(usize, LazyClient)>,
+    next:    usize,
+    next_id: usize,
+}
+
+impl AMQPPool {
+    /// Create a new pool opening its connections using `builder`
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn new(builder: AMQPConnectionBuilder, max_connections: usize) -> AMQPPool {
+        AMQPPool {
+            builder,
+            max_connections: max_connections.max(1),
+            connections:     Arc::new(Mutex::new(Connections::default())),
+        }
+    }
+
+    /// The maximum number of connections opened by this pool
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Create a `lapin_futures::channel::Channel` on one of the connections of the pool
+    ///
+    /// If the connection fails, it gets replaced by a new one which is used to retry once. The errors which don't
+    /// affect the connection itself, e.g. the broker `channel_max` being reached, are returned without replacing it.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn acquire(&self) -> Box<dyn Future<Item = Channel<AMQPStream>, Error = Error> + Send + 'static> {
+        let (id, client) = self.pick();
+        let pool         = self.clone();
+        Box::new(client.client().map_err(|err| (true, err)).and_then(|client| {
+            client.create_channel().map_err(|err| (is_connection_error(&err), ErrorKind::ProtocolError(err).into()))
+        }).or_else(move |(failed, err)| {
+            if !failed {
+                return Either::A(future::err(err));
+            }
+            warn!("pooled connection {} failed, reconnecting: {}", id, err);
+            Either::B(pool.replace(id).create_channel())
+        }))
+    }
+
+    fn pick(&self) -> (usize, LazyClient) {
+        let mut connections = self.lock();
+        if connections.clients.len() < self.max_connections {
+            let id = connections.next_id;
+            connections.next_id += 1;
+            connections.clients.push((id, self.connect()));
+        }
+        let index = connections.next % connections.clients.len();
+        connections.next = connections.next.wrapping_add(1);
+        connections.clients[index].clone()
+    }
+
+    fn replace(&self, id: usize) -> LazyClient {
+        let mut connections = self.lock();
+        let new_id          = connections.next_id;
+        match connections.clients.iter().position(|&(client_id, _)| client_id == id) {
+            Some(index) => {
+                connections.next_id += 1;
+                let client = self.connect();
+                // Dropping the evicted client doesn't stop its heartbeat, which would keep a half-dead connection open
+                connections.clients[index].1.stop_established_heartbeat();
+                connections.clients[index] = (new_id, client.clone());
+                client
+            },
+            // Another task already replaced it
+            None        => {
+                drop(connections);
+                self.pick().1
+            },
+        }
+    }
+
+    fn connect(&self) -> LazyClient {
+        let builder = self.builder.clone();
+        LazyClient::new(future::lazy(move || builder.connect_cancellable(|err| warn!("pooled connection heartbeat error: {}", err))))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connections> {
+        // The lock is never held while doing anything which could panic
+        self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Whether `err` means that the connection stopped working, rather than only the channel creation failing
+pub(crate) fn is_connection_error(err: &ProtocolError) -> bool {
+    match *err.kind() {
+        ProtocolErrorKind::ConnectionClosed | ProtocolErrorKind::ConnectionFailed(_) | ProtocolErrorKind::Decode(_) | ProtocolErrorKind::Encode(_) | ProtocolErrorKind::HeartbeatTimer(_) => true,
+        _                                                                                                                                                                      => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+
+    #[test]
+    fn only_connection_errors_evict() {
+        assert!(is_connection_error(&ProtocolErrorKind::ConnectionClosed.into()));
+        assert!(is_connection_error(&ProtocolErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")).into()));
+        assert!(!is_connection_error(&ProtocolErrorKind::ChannelLimitReached.into()));
+        assert!(!is_connection_error(&ProtocolErrorKind::ChannelOpenFailed.into()));
+    }
+}
@@ -0,0 +1,238 @@
+use futures::future::{self, Future, Shared};
+use lapin_futures_tls_internal::{error::{Error, ErrorKind}, RetryPolicy};
+use log::warn;
+
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+use crate::{health, lapin, lazy, pool, AMQPConnectionBuilder, AMQPStream};
+use lapin::channel::{Channel, ConfirmSelectOptions};
+use lapin::client::{Client, HeartbeatHandle};
+
+type Setup      = Arc<dyn Fn(Client<AMQPStream>) -> Box<dyn Future<Item = (), Error = Error> + Send + 'static> + Send + Sync>;
+type Connection = Shared<Box<dyn Future<Item = Client<AMQPStream>, Error = Error> + Send + 'static>>;
+
+/// A `lapin_futures::client::Client` which reconnects to the broker when the connection is lost.
+///
+/// The connection is established when first used. Once the heartbeat reports the connection as lost, or a
+/// channel can't be created on it anymore, a new one is opened using the `RetryPolicy` and all the setups
+/// registered with `register` are run again on it, e.g. to declare queues and start consumers.
+/// The errors which leave the connection working, e.g. the broker `channel_max` being reached, are returned as is.
+#[derive(Clone)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct ReconnectingClient {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    builder: AMQPConnectionBuilder,
+    policy:  RetryPolicy,
+    state:   Mutex<State>,
+}
+
+struct State {
+    connection: Option<(usize, Connection)>,
+    generation: usize,
+    ready:      Option<usize>,
+    heartbeat:  Option<HeartbeatHandle>,
+    setups:     Vec<Setup>,
+}
+
+impl ReconnectingClient {
+    /// Create a new client opening its connections using `builder`, retrying them according to `policy`
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn new(builder: AMQPConnectionBuilder, policy: RetryPolicy) -> ReconnectingClient {
+        ReconnectingClient {
+            inner: Arc::new(Inner {
+                builder,
+                policy,
+                state: Mutex::new(State {
+                    connection: None,
+                    generation: 0,
+                    ready:      None,
+                    heartbeat:  None,
+                    setups:     Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Method providing the current `lapin_futures::client::Client` wrapped in a `Future`, connecting to the broker if needed
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn client(&self) -> impl Future<Item = Client<AMQPStream>, Error = Error> + Send + 'static {
+        self.inner.connection().1.map(|client| (*client).clone()).map_err(lazy::shared_error)
+    }
+
+    /// Run `setup` on the current connection, and again on each new connection after a reconnection
+    ///
+    /// The channels and consumers created by `setup` are lost along with the connection, this is
+    /// where they should be created so that they get re-established.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn register<F: Fn(Client<AMQPStream>) -> Fut + Send + Sync + 'static, Fut: Future<Item = (), Error = Error> + Send + 'static>(&self, setup: F) -> impl Future<Item = (), Error = Error> + Send + 'static {
+        let setup: Setup = Arc::new(move |client| Box::new(setup(client)) as Box<dyn Future<Item = (), Error = Error> + Send + 'static>);
+        let (connection, run) = {
+            let mut state                = self.inner.lock();
+            let (generation, connection) = self.inner.current(&mut state);
+            state.setups.push(setup.clone());
+            // A connection still being established will run the setup along with the other ones
+            (connection, state.ready == Some(generation))
+        };
+        connection.map_err(lazy::shared_error).and_then(move |client| {
+            if run {
+                future::Either::A(setup((*client).clone()))
+            } else {
+                future::Either::B(future::ok(()))
+            }
+        })
+    }
+
+    /// Create a `lapin_futures::channel::Channel`, reconnecting once if the current connection failed
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn create_channel(&self) -> impl Future<Item = Channel<AMQPStream>, Error = Error> + Send + 'static {
+        self.with_client(|client| Box::new(client.create_channel().map_err(|e| ErrorKind::ProtocolError(e).into())))
+    }
+
+    /// Create a `lapin_futures::channel::Channel` with publisher confirms, reconnecting once if the current connection failed
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn create_confirm_channel(&self, options: ConfirmSelectOptions) -> impl Future<Item = Channel<AMQPStream>, Error = Error> + Send + 'static {
+        self.with_client(move |client| Box::new(client.create_confirm_channel(options.clone()).map_err(|e| ErrorKind::ProtocolError(e).into())))
+    }
+
+    /// Check that the current connection is alive by opening and closing a channel on it, without reconnecting
+    ///
+    /// The `Future` resolves to `false` if it fails or if the broker doesn't respond within `timeout`.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn ping(&self, timeout: Duration) -> impl Future<Item = bool, Error = Error> + Send + 'static {
+        health::ping(self.client(), timeout)
+    }
+
+    fn with_client<T: Send + 'static, F: Fn(&Client<AMQPStream>) -> Box<dyn Future<Item = T, Error = Error> + Send + 'static> + Send + Sync + 'static>(&self, f: F) -> impl Future<Item = T, Error = Error> + Send + 'static {
+        let (generation, connection) = self.inner.connection();
+        let inner                    = self.inner.clone();
+        let f                        = Arc::new(f);
+        let retry                    = f.clone();
+        connection.map_err(lazy::shared_error).and_then(move |client| f(&client)).or_else(move |err| {
+            if !is_connection_lost(&err) {
+                return future::Either::A(future::err(err));
+            }
+            warn!("connection failed, reconnecting: {}", err);
+            future::Either::B(inner.reconnect(generation).map_err(lazy::shared_error).and_then(move |client| retry(&client)))
+        })
+    }
+}
+
+impl Inner {
+    fn connection(self: &Arc<Self>) -> (usize, Connection) {
+        let mut state = self.lock();
+        self.current(&mut state)
+    }
+
+    fn current(self: &Arc<Self>, state: &mut State) -> (usize, Connection) {
+        if state.connection.is_none() {
+            let generation   = state.generation;
+            state.connection = Some((generation, self.connect(generation)));
+        }
+        state.connection.clone().expect("connection was just set")
+    }
+
+    /// Replace the connection of the given generation, unless another task already did
+    fn reconnect(self: &Arc<Self>, generation: usize) -> Connection {
+        let mut state = self.lock();
+        if let Some((current, ref connection)) = state.connection {
+            if current != generation {
+                return connection.clone();
+            }
+        }
+        if let Some(heartbeat_handle) = state.heartbeat.take() {
+            heartbeat_handle.stop();
+        }
+        state.generation += 1;
+        let generation    = state.generation;
+        let connection    = self.connect(generation);
+        state.connection  = Some((generation, connection.clone()));
+        connection
+    }
+
+    fn connect(self: &Arc<Self>, generation: usize) -> Connection {
+        let builder = self.builder.clone();
+        let policy  = self.policy.clone();
+        let inner   = Arc::downgrade(self);
+        let connect = future::lazy(move || policy.retry(move || builder.clone().connect())).and_then(move |(client, heartbeat_handle, heartbeat)| {
+            let setups = inner.upgrade().map(|inner| {
+                let mut state   = inner.lock();
+                // Dropping the previous handle wouldn't stop its heartbeat, which would keep that connection open
+                if let Some(previous) = state.heartbeat.take() {
+                    previous.stop();
+                }
+                state.heartbeat = Some(heartbeat_handle);
+                state.ready     = Some(generation);
+                state.setups.clone()
+            }).unwrap_or_default();
+            tokio_executor::spawn(heartbeat.then(move |res| {
+                if let Err(err) = res {
+                    reconnect_lost(&inner, generation, &err);
+                }
+                Ok(())
+            }));
+            future::join_all(setups.into_iter().map(|setup| setup(client.clone())).collect::<Vec<_>>()).map(move |_| client)
+        });
+        let connection: Box<dyn Future<Item = Client<AMQPStream>, Error = Error> + Send + 'static> = Box::new(connect);
+        connection.shared()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The lock is never held while doing anything which could panic
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Some(heartbeat_handle) = self.lock().heartbeat.take() {
+            heartbeat_handle.stop();
+        }
+    }
+}
+
+/// Whether `err` means that the connection is gone, rather than e.g. a channel creation or a setup failing on a healthy one
+fn is_connection_lost(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::ConnectionFailed(_)   |
+        ErrorKind::TlsHandshakeFailed(_) |
+        ErrorKind::HandshakeTimedOut     => true,
+        ErrorKind::ProtocolError(ref e)  => pool::is_connection_error(e),
+        _                                => false,
+    }
+}
+
+/// The heartbeat failed, eagerly reconnect so that the registered setups run again without waiting for the next use
+fn reconnect_lost(inner: &Weak<Inner>, generation: usize, err: &Error) {
+    if let Some(inner) = inner.upgrade() {
+        warn!("connection lost, reconnecting: {}", err);
+        tokio_executor::spawn(inner.reconnect(generation).then(|res| {
+            if let Err(err) = res {
+                warn!("reconnection failed: {}", err);
+            }
+            Ok(())
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lapin::error::ErrorKind as ProtocolErrorKind;
+
+    use std::io;
+
+    #[test]
+    fn only_connection_errors_reconnect() {
+        assert!(is_connection_lost(&ErrorKind::ConnectionFailed(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")).into()));
+        assert!(is_connection_lost(&ErrorKind::HandshakeTimedOut.into()));
+        assert!(is_connection_lost(&ErrorKind::ProtocolError(ProtocolErrorKind::ConnectionClosed.into()).into()));
+        assert!(!is_connection_lost(&ErrorKind::ProtocolError(ProtocolErrorKind::ChannelLimitReached.into()).into()));
+        assert!(!is_connection_lost(&ErrorKind::ProtocolError(ProtocolErrorKind::ChannelOpenFailed.into()).into()));
+        assert!(!is_connection_lost(&ErrorKind::Cancelled.into()));
+    }
+}
@@ -0,0 +1,92 @@
+use lapin_futures_tls_internal::{self, TcpStream};
+use tokio_rustls::rustls::{Certificate, CipherSuite, ClientSession, ProtocolVersion, Session};
+
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+use std::time::SystemTime;
+
+use crate::{cert, AMQPStream};
+
+/// Add methods giving access to the TLS session of an `AMQPStream`.
+#[deprecated(note = "use lapin directly instead")]
+pub trait AMQPStreamRustlsExt {
+    /// The ALPN protocol negotiated during the TLS handshake, `None` for raw connections or if none was negotiated
+    fn alpn_protocol(&self) -> Option<&[u8]>;
+    /// The TLS version negotiated during the handshake, `None` for raw connections
+    fn protocol_version(&self) -> Option<ProtocolVersion>;
+    /// The cipher suite negotiated during the handshake, `None` for raw connections
+    fn negotiated_cipher_suite(&self) -> Option<CipherSuite>;
+    /// The certificate chain presented by the broker, `None` for raw connections
+    fn peer_certificates(&self) -> Option<Vec<Certificate>>;
+    /// The end of the validity period of the broker certificate, `None` for raw connections or if it can't be parsed
+    fn peer_cert_not_after(&self) -> Option<SystemTime>;
+    /// Derive `out_len` bytes of keying material from the TLS session as described in RFC 5705, an error for raw connections
+    fn export_keying_material(&self, label: &[u8], context: Option<&[u8]>, out_len: usize) -> io::Result<Vec<u8>>;
+    /// The address of the broker end of the underlying `TcpStream`, an error for Unix domain socket connections
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    /// The address of the local end of the underlying `TcpStream`, an error for Unix domain socket connections
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    /// Shut down the write half of the underlying `TcpStream` without sending a TLS `close_notify` first
+    ///
+    /// This works around brokers logging a warning when receiving a `close_notify`, `AsyncWrite::shutdown`
+    /// still sends it. It's the same as `AMQPStream::shutdown_write` for raw connections.
+    fn shutdown_without_close_notify(&self) -> io::Result<()>;
+}
+
+impl AMQPStreamRustlsExt for AMQPStream {
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        session(self).and_then(|session| session.get_alpn_protocol())
+    }
+
+    fn protocol_version(&self) -> Option<ProtocolVersion> {
+        session(self).and_then(|session| session.get_protocol_version())
+    }
+
+    fn negotiated_cipher_suite(&self) -> Option<CipherSuite> {
+        session(self).and_then(|session| session.get_negotiated_ciphersuite()).map(|suite| suite.suite)
+    }
+
+    fn peer_certificates(&self) -> Option<Vec<Certificate>> {
+        session(self).and_then(|session| session.get_peer_certificates())
+    }
+
+    fn peer_cert_not_after(&self) -> Option<SystemTime> {
+        self.peer_certificates().and_then(|certs| certs.first().and_then(cert::not_after))
+    }
+
+    fn export_keying_material(&self, label: &[u8], context: Option<&[u8]>, out_len: usize) -> io::Result<Vec<u8>> {
+        let session    = session(self).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Not a TLS connection"))?;
+        let mut output = vec![0u8; out_len];
+        session.export_keying_material(&mut output, label, context).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        Ok(output)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        tcp_stream(self)?.peer_addr()
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        tcp_stream(self)?.local_addr()
+    }
+
+    fn shutdown_without_close_notify(&self) -> io::Result<()> {
+        if self.is_tls() {
+            tcp_stream(self)?.shutdown(Shutdown::Write)
+        } else {
+            self.shutdown_write()
+        }
+    }
+}
+
+fn session(stream: &AMQPStream) -> Option<&ClientSession> {
+    stream.tls_stream().map(|tls| tls.get_ref().1)
+}
+
+fn tcp_stream(stream: &AMQPStream) -> io::Result<&TcpStream> {
+    match *stream {
+        lapin_futures_tls_internal::AMQPStream::Raw(ref raw) => Ok(raw),
+        lapin_futures_tls_internal::AMQPStream::Tls(ref tls) => Ok(tls.get_ref().0),
+        #[cfg(unix)]
+        lapin_futures_tls_internal::AMQPStream::Uds(_)       => Err(io::Error::new(io::ErrorKind::Other, "Not a TCP connection")),
+    }
+}
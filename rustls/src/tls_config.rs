@@ -0,0 +1,222 @@
+#[cfg(feature = "native-roots")]
+use log::warn;
+use tokio_rustls::rustls::{ClientConfig, ClientSessionMemoryCache, KeyLog, KeyLogFile, ProtocolVersion, SupportedCipherSuite};
+#[cfg(feature = "dangerous-tls")]
+use tokio_rustls::{rustls::{Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError}, webpki};
+
+use std::sync::Arc;
+#[cfg(feature = "dangerous-tls")]
+use std::time::SystemTime;
+#[cfg(feature = "native-roots")]
+use std::{fs, io};
+
+/// A builder for the `rustls::ClientConfig` to pass to the `connect_with_config` methods.
+///
+/// It starts from the default configuration used by `connect`, trusting the `webpki_roots` certificates.
+#[derive(Clone)]
+#[deprecated(note = "use lapin directly instead")]
+pub struct TlsConfigBuilder {
+    config: ClientConfig,
+}
+
+impl TlsConfigBuilder {
+    /// Create a new builder using the default configuration
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn new() -> TlsConfigBuilder {
+        let mut config = ClientConfig::new();
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        TlsConfigBuilder { config }
+    }
+
+    /// Create a new builder trusting the certificates from the system trust store instead of the `webpki_roots` ones
+    #[cfg(feature = "native-roots")]
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn with_native_roots() -> io::Result<TlsConfigBuilder> {
+        TlsConfigBuilder { config: ClientConfig::new() }.add_native_roots()
+    }
+
+    /// Trust the certificates from the system trust store on top of the already trusted ones
+    ///
+    /// This fails if no certificate could be loaded from the system trust store, an unreadable certificates directory is only logged.
+    #[cfg(feature = "native-roots")]
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn add_native_roots(mut self) -> io::Result<TlsConfigBuilder> {
+        let probe     = openssl_probe::probe();
+        let mut files = probe.cert_file.into_iter().collect::<Vec<_>>();
+        if let Some(dir) = probe.cert_dir {
+            // The certificate bundle may be enough on its own, only fail if no certificate was found at all
+            match fs::read_dir(&dir) {
+                Ok(entries) => files.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file())),
+                Err(err)    => warn!("failed to read the certificates directory {}: {}", dir.display(), err),
+            }
+        }
+        let mut added = 0;
+        for file in files {
+            // Skip the files which aren't PEM certificates, trust stores often contain other things
+            if let Ok(file) = fs::File::open(file) {
+                if let Ok((valid, _)) = self.config.root_store.add_pem_file(&mut io::BufReader::new(file)) {
+                    added += valid;
+                }
+            }
+        }
+        if added == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No certificate found in the system trust store"));
+        }
+        Ok(self)
+    }
+
+    /// Restrict the TLS protocol versions accepted during the handshake
+    ///
+    /// The handshake fails if the broker doesn't support any of them.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn versions(mut self, versions: &[ProtocolVersion]) -> TlsConfigBuilder {
+        self.config.versions = versions.to_vec();
+        self
+    }
+
+    /// Restrict the ciphersuites offered to the broker, in order of preference
+    ///
+    /// The handshake fails with an `ErrorKind::TlsHandshakeFailed` error if the broker doesn't support any of them.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn ciphersuites(mut self, ciphersuites: &[&'static SupportedCipherSuite]) -> TlsConfigBuilder {
+        self.config.ciphersuites = ciphersuites.to_vec();
+        self
+    }
+
+    /// Set the ALPN protocol identifiers to offer to the broker during the handshake
+    ///
+    /// The negotiated one can then be retrieved using `AMQPStreamRustlsExt::alpn_protocol`.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn alpn_protocols(mut self, protocols: &[Vec<u8>]) -> TlsConfigBuilder {
+        self.config.set_protocols(protocols);
+        self
+    }
+
+    /// Set whether to send the SNI extension during the handshake, enabled by default
+    ///
+    /// The certificate is still verified against the host, or the name given to `connect_with_sni`.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn enable_sni(mut self, enable: bool) -> TlsConfigBuilder {
+        self.config.enable_sni = enable;
+        self
+    }
+
+    /// Set how many TLS sessions to keep in memory for resumption, 32 by default
+    ///
+    /// Sessions are only resumed when reusing the same `rustls::ClientConfig` across connections.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn session_cache_size(mut self, size: usize) -> TlsConfigBuilder {
+        self.config.set_persistence(ClientSessionMemoryCache::new(size));
+        self
+    }
+
+    /// Replace the verification of the certificate presented by the broker with `verifier`
+    ///
+    /// **Dangerous**: `verifier` is fully responsible for validating the certificate chain and the server name.
+    #[cfg(feature = "dangerous-tls")]
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn certificate_verifier(mut self, verifier: Arc<dyn ServerCertVerifier>) -> TlsConfigBuilder {
+        self.config.dangerous().set_certificate_verifier(verifier);
+        self
+    }
+
+    /// Verify the certificate chain presented by the broker against the trusted roots without checking it against the server name
+    ///
+    /// **Dangerous**: any certificate issued by a trusted root is accepted, whatever the host it was issued for.
+    /// This replaces the verifier set by `certificate_verifier`.
+    #[cfg(feature = "dangerous-tls")]
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn disable_hostname_verification(self) -> TlsConfigBuilder {
+        self.certificate_verifier(Arc::new(NoHostnameVerification))
+    }
+
+    /// Send the TLS secrets of each session to `key_log`, to decrypt the traffic when debugging
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn key_log(mut self, key_log: Arc<dyn KeyLog>) -> TlsConfigBuilder {
+        self.config.key_log = key_log;
+        self
+    }
+
+    /// Write the TLS secrets of each session to the file named by the `SSLKEYLOGFILE` environment variable, if set
+    ///
+    /// The file uses the NSS key log format understood by Wireshark.
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn key_log_file(self) -> TlsConfigBuilder {
+        self.key_log(Arc::new(KeyLogFile::new()))
+    }
+
+    /// Build the `rustls::ClientConfig`
+    #[deprecated(note = "use lapin directly instead")]
+    pub fn build(self) -> Arc<ClientConfig> {
+        Arc::new(self.config)
+    }
+
+    pub(crate) fn into_inner(self) -> ClientConfig {
+        self.config
+    }
+}
+
+impl Default for TlsConfigBuilder {
+    fn default() -> TlsConfigBuilder {
+        TlsConfigBuilder::new()
+    }
+}
+
+/// The signature algorithms accepted by the default rustls verifier
+#[cfg(feature = "dangerous-tls")]
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// The checks of the default rustls verifier, which isn't exported, but the server name one
+#[cfg(feature = "dangerous-tls")]
+pub(crate) struct NoHostnameVerification;
+
+#[cfg(feature = "dangerous-tls")]
+impl ServerCertVerifier for NoHostnameVerification {
+    fn verify_server_cert(&self, roots: &RootCertStore, presented_certs: &[Certificate], _dns_name: webpki::DNSNameRef<'_>, _ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let (cert, chain) = presented_certs.split_first().ok_or(TLSError::NoCertificatesPresented)?;
+        let chain         = chain.iter().map(|cert| untrusted::Input::from(&cert.0)).collect::<Vec<_>>();
+        let anchors       = roots.roots.iter().map(|root| root.to_trust_anchor()).collect::<Vec<_>>();
+        let now           = webpki::Time::try_from(SystemTime::now()).map_err(|_| TLSError::FailedToGetCurrentTime)?;
+        webpki::EndEntityCert::from(untrusted::Input::from(&cert.0)).and_then(|cert| {
+            cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS, &webpki::TLSServerTrustAnchors(&anchors), &chain, now)
+        }).map(|()| ServerCertVerified::assertion()).map_err(TLSError::WebPKIError)
+    }
+}
+
+#[cfg(all(test, feature = "dangerous-tls"))]
+mod tests {
+    use super::*;
+
+    fn verify(certs: &[Certificate]) -> Result<ServerCertVerified, TLSError> {
+        let name = webpki::DNSNameRef::try_from_ascii_str("broker.example").unwrap();
+        NoHostnameVerification.verify_server_cert(&TlsConfigBuilder::new().into_inner().root_store, certs, name, &[])
+    }
+
+    #[test]
+    fn reject_missing_certificate() {
+        match verify(&[]) {
+            Err(TLSError::NoCertificatesPresented) => {},
+            res                                    => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_certificate() {
+        match verify(&[Certificate(b"not a certificate".to_vec())]) {
+            Err(TLSError::WebPKIError(webpki::Error::BadDER)) => {},
+            res                                               => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+#![cfg(all(feature = "blocking", feature = "test-util"))]
+#![allow(deprecated)]
+
+use futures::future::Future;
+use lapin_futures_rustls::AMQPConnectionBuilder;
+use lapin_futures_rustls::test_util::{MockBroker, MockFailure};
+use tokio::runtime::Runtime;
+
+fn builder(broker: &MockBroker) -> AMQPConnectionBuilder {
+    broker.uri().parse().unwrap()
+}
+
+#[test]
+fn connect_blocking_reports_negotiation_failures() {
+    let mut runtime = Runtime::new().unwrap();
+    let broker      = MockBroker::start(vec![MockFailure::CloseAfterStart]).unwrap();
+    assert!(builder(&broker).connect_blocking(&mut runtime).is_err());
+    assert_eq!(broker.connections(), 1);
+}
+
+// lapin's heartbeat relies on tokio-sync 0.1's oneshot, which calls mem::uninitialized and aborts on recent compilers
+#[test]
+#[ignore = "completing the negotiation aborts with tokio-sync 0.1 on recent compilers"]
+fn connect_blocking_heartbeat_receiver_is_cancelled_when_stopped() {
+    let mut runtime                         = Runtime::new().unwrap();
+    let broker                              = MockBroker::start(vec![]).unwrap();
+    let (_client, heartbeat_handle, errors) = builder(&broker).connect_blocking(&mut runtime).unwrap();
+    heartbeat_handle.stop();
+    assert!(errors.wait().is_err());
+}
@@ -0,0 +1,54 @@
+#![allow(deprecated)]
+
+use futures::future::Future;
+use lapin_futures_rustls::AMQPConnectionBuilder;
+use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// Start connecting to a listener which never answers, drop the connect future, and return the accepted peer
+fn drop_connect_mid_handshake(runtime: &mut Runtime, listener: &TcpListener, scheme: &str) -> TcpStream {
+    let builder = format!("{}://guest:guest@127.0.0.1:{}/%2f", scheme, listener.local_addr().unwrap().port()).parse::<AMQPConnectionBuilder>().unwrap().server_name("localhost");
+    // The Timeout drops the connect future once it elapses, the handshake being stuck
+    match runtime.block_on(Timeout::new(builder.connect().map(|_| ()), Duration::from_millis(200))) {
+        Err(ref err) if err.is_elapsed() => {},
+        Err(err)                         => panic!("connect failed: {}", err),
+        Ok(())                           => panic!("connect succeeded"),
+    }
+    listener.accept().unwrap().0
+}
+
+fn assert_eof(mut peer: TcpStream) {
+    peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut handshake = Vec::new();
+    // The client sent its protocol header or ClientHello, then closed the socket
+    peer.read_to_end(&mut handshake).expect("the client didn't close the socket");
+    assert!(!handshake.is_empty());
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+// A single test, as the open fds of the whole process are counted
+#[test]
+fn dropping_connect_closes_the_socket() {
+    let mut runtime = Runtime::new().unwrap();
+    let listener    = TcpListener::bind("127.0.0.1:0").unwrap();
+    // Stuck in the AMQP negotiation, then in the TLS handshake
+    assert_eof(drop_connect_mid_handshake(&mut runtime, &listener, "amqp"));
+    assert_eof(drop_connect_mid_handshake(&mut runtime, &listener, "amqps"));
+
+    #[cfg(target_os = "linux")]
+    {
+        let before = open_fds();
+        for scheme in ["amqp", "amqps"].iter().cycle().take(10) {
+            assert_eof(drop_connect_mid_handshake(&mut runtime, &listener, scheme));
+        }
+        assert_eq!(open_fds(), before);
+    }
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "test-util")]
+#![allow(deprecated)]
+
+use futures::future::Future;
+use lapin_futures_rustls::{AMQPConnectionBuilder, AMQPConnectionRustlsExt, AMQPPool, RetryPolicy};
+use lapin_futures_rustls::error::ErrorKind;
+use lapin_futures_rustls::test_util::{MockBroker, MockFailure};
+use tokio::runtime::Runtime;
+
+use std::time::Duration;
+
+fn builder(broker: &MockBroker) -> AMQPConnectionBuilder {
+    broker.uri().parse().unwrap()
+}
+
+fn hosts(brokers: &[&MockBroker]) -> Vec<(String, u16)> {
+    brokers.iter().map(|broker| (broker.addr().ip().to_string(), broker.addr().port())).collect()
+}
+
+#[test]
+fn broker_closing_during_negotiation_fails_connect() {
+    let mut runtime = Runtime::new().unwrap();
+    for failure in [MockFailure::CloseOnAccept, MockFailure::CloseAfterHeader, MockFailure::CloseAfterStart].iter() {
+        let broker = MockBroker::start(vec![*failure]).unwrap();
+        match runtime.block_on(builder(&broker).connect()) {
+            Ok(_)    => panic!("connecting should fail with {:?}", failure),
+            Err(err) => match *err.kind() {
+                ErrorKind::ProtocolError(_) => {},
+                ref kind                    => panic!("unexpected error with {:?}: {}", failure, kind),
+            },
+        }
+        assert_eq!(broker.connections(), 1);
+    }
+}
+
+#[test]
+fn protocol_errors_are_not_retried() {
+    let mut runtime = Runtime::new().unwrap();
+    let broker      = MockBroker::start(vec![MockFailure::CloseAfterStart; 3]).unwrap();
+    let builder     = builder(&broker);
+    let policy      = RetryPolicy { max_attempts: 3, initial_delay: Duration::from_millis(10), ..RetryPolicy::default() };
+    assert!(runtime.block_on(policy.retry(move || builder.clone().connect())).is_err());
+    assert_eq!(broker.connections(), 1);
+}
+
+#[test]
+fn connect_to_any_tries_every_broker() {
+    let mut runtime = Runtime::new().unwrap();
+    let first       = MockBroker::start(vec![MockFailure::CloseOnAccept]).unwrap();
+    let second      = MockBroker::start(vec![MockFailure::CloseAfterHeader]).unwrap();
+    assert!(runtime.block_on(builder(&first).connect_to_any(&hosts(&[&first, &second]))).is_err());
+    assert_eq!((first.connections(), second.connections()), (1, 1));
+}
+
+#[test]
+fn pool_replaces_failed_connections_once() {
+    let mut runtime = Runtime::new().unwrap();
+    let broker      = MockBroker::start(vec![MockFailure::CloseAfterStart; 3]).unwrap();
+    let pool        = AMQPPool::new(builder(&broker), 1);
+    assert!(runtime.block_on(pool.acquire()).is_err());
+    assert_eq!(broker.connections(), 2);
+}
+
+#[test]
+fn unsupported_verify_hostname_fails_the_handshake() {
+    let mut runtime = Runtime::new().unwrap();
+    let broker      = MockBroker::start(vec![]).unwrap();
+    let uri         = broker.uri().replacen("amqp://", "amqps://", 1) + "?verify_hostname=false";
+    match runtime.block_on(uri.as_str().connect_stream(Default::default())) {
+        Ok(_)    => panic!("the default connector should reject verify_hostname=false"),
+        Err(err) => match *err.kind() {
+            ErrorKind::TlsHandshakeFailed(ref err) => assert!(err.to_string().contains("verify_hostname"), "{}", err),
+            ref kind                               => panic!("unexpected error: {}", kind),
+        },
+    }
+}
+
+// lapin's heartbeat relies on tokio-sync 0.1's oneshot, which calls mem::uninitialized and aborts on recent compilers
+#[test]
+#[ignore = "completing the negotiation aborts with tokio-sync 0.1 on recent compilers"]
+fn connect_to_any_fails_over_to_a_working_broker() {
+    let mut runtime = Runtime::new().unwrap();
+    let failing     = MockBroker::start(vec![MockFailure::CloseAfterStart]).unwrap();
+    let working     = MockBroker::start(Vec::new()).unwrap();
+    let channel     = runtime.block_on(builder(&failing).connect_to_any(&hosts(&[&failing, &working])).and_then(|(client, heartbeat_handle, _)| {
+        heartbeat_handle.stop();
+        client.create_channel().map_err(|e| ErrorKind::ProtocolError(e).into())
+    })).unwrap();
+    assert_eq!(channel.id, 1);
+    assert_eq!((failing.connections(), working.connections()), (1, 1));
+}
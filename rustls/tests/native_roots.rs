@@ -0,0 +1,17 @@
+#![cfg(feature = "native-roots")]
+#![allow(deprecated)]
+
+use lapin_futures_rustls::TlsConfigBuilder;
+
+use std::env;
+use std::path::Path;
+
+#[test]
+fn unreadable_cert_dir_is_skipped() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    env::set_var("SSL_CERT_FILE", fixtures.join("ca.pem"));
+    // Exists but isn't a directory, like a broken SSL_CERT_DIR
+    env::set_var("SSL_CERT_DIR", fixtures.join("ca.pem"));
+    let config = TlsConfigBuilder::with_native_roots().unwrap().build();
+    assert_eq!(config.root_store.len(), 1);
+}
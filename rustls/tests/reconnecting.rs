@@ -0,0 +1,42 @@
+#![cfg(feature = "test-util")]
+#![allow(deprecated)]
+
+use futures::future;
+use lapin_futures_rustls::{AMQPConnectionBuilder, AMQPStream, ReconnectingClient, RetryPolicy};
+use lapin_futures_rustls::error::Error;
+use lapin_futures_rustls::lapin::client::Client;
+use lapin_futures_rustls::test_util::MockBroker;
+use tokio::runtime::Runtime;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn counting_setup(calls: &Arc<AtomicUsize>) -> impl Fn(Client<AMQPStream>) -> future::FutureResult<(), Error> + Send + Sync + 'static {
+    let calls = calls.clone();
+    move |_| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        future::ok(())
+    }
+}
+
+// lapin's heartbeat relies on tokio-sync 0.1's oneshot, which calls mem::uninitialized and aborts on recent compilers
+#[test]
+#[ignore = "completing the negotiation aborts with tokio-sync 0.1 on recent compilers"]
+fn register_runs_each_setup_once() {
+    let mut runtime = Runtime::new().unwrap();
+    let broker      = MockBroker::start(Vec::new()).unwrap();
+    let builder     = broker.uri().parse::<AMQPConnectionBuilder>().unwrap();
+    let client      = ReconnectingClient::new(builder, RetryPolicy::default());
+    let before      = Arc::new(AtomicUsize::new(0));
+    let after       = Arc::new(AtomicUsize::new(0));
+
+    // Registered before the first connection: run by the connection itself
+    runtime.block_on(client.register(counting_setup(&before))).unwrap();
+    assert_eq!(before.load(Ordering::SeqCst), 1);
+
+    // Registered once connected: run right away on the current connection
+    runtime.block_on(client.register(counting_setup(&after))).unwrap();
+    runtime.block_on(client.create_channel()).unwrap();
+    assert_eq!((before.load(Ordering::SeqCst), after.load(Ordering::SeqCst)), (1, 1));
+    assert_eq!(broker.connections(), 1);
+}